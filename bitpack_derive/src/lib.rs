@@ -0,0 +1,330 @@
+//! `#[derive(BitPack)]` for the cart's `bitpack` module. The generated code
+//! refers to `crate::bitpack`, so it's only usable inside the cart crate.
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, parse_quote, Attribute, Data, DeriveInput, Error, Fields, Index, Lit, Meta,
+    NestedMeta, Type,
+};
+
+#[proc_macro_derive(BitPack, attributes(bits))]
+pub fn derive_bitpack(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// The width given with `#[bits(n)]`, if any.
+fn bits_attr(attrs: &[Attribute]) -> syn::Result<Option<usize>> {
+    let attr = match attrs.iter().find(|a| a.path.is_ident("bits")) {
+        Some(attr) => attr,
+        None => return Ok(None),
+    };
+    let meta = attr.parse_meta()?;
+    if let Meta::List(list) = &meta {
+        if let [NestedMeta::Lit(Lit::Int(int))] = list.nested.iter().collect::<Vec<_>>()[..] {
+            let bits = int.base10_parse::<usize>()?;
+            if !(1..=32).contains(&bits) {
+                return Err(Error::new_spanned(int, "bits must be 1 to 32"));
+            }
+            return Ok(Some(bits));
+        }
+    }
+    Err(Error::new_spanned(meta, "expected #[bits(n)]"))
+}
+
+/// The size of the integer types `#[bits(n)]` applies to.
+fn primitive_width(ty: &Type) -> Option<usize> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+    let ident = path.path.get_ident()?;
+    match ident.to_string().as_str() {
+        "u8" | "i8" => Some(8),
+        "u16" | "i16" => Some(16),
+        "u32" | "i32" => Some(32),
+        _ => None,
+    }
+}
+
+/// Size, pack and unpack code for a list of fields. `access` gives a
+/// reference to each field's value for packing.
+struct FieldsCode {
+    bits: TokenStream2,
+    pack: TokenStream2,
+    unpack: TokenStream2,
+}
+
+fn fields_code(fields: &Fields, access: &[TokenStream2]) -> syn::Result<FieldsCode> {
+    let mut bits = vec![quote!(0usize)];
+    let mut pack = Vec::new();
+    let mut values = Vec::new();
+    for (field, access) in fields.iter().zip(access) {
+        let ty = &field.ty;
+        match bits_attr(&field.attrs)? {
+            Some(n) => {
+                if let Some(width) = primitive_width(ty) {
+                    if n > width {
+                        return Err(Error::new_spanned(
+                            ty,
+                            format!(
+                                "#[bits({})] is wider than the {} bits of the field",
+                                n, width
+                            ),
+                        ));
+                    }
+                }
+                bits.push(quote!(#n));
+                pack.push(quote! {
+                    crate::bitpack::BitPackBits::pack_bits(#access, writer, #n);
+                });
+                values.push(quote! {
+                    <#ty as crate::bitpack::BitPackBits>::unpack_bits(reader, #n)
+                });
+            }
+            None => {
+                bits.push(quote!(<#ty as crate::bitpack::BitPack>::BITS));
+                pack.push(quote! {
+                    crate::bitpack::BitPack::pack(#access, writer);
+                });
+                values.push(quote! {
+                    <#ty as crate::bitpack::BitPack>::unpack(reader)
+                });
+            }
+        }
+    }
+    let unpack = match fields {
+        Fields::Named(named) => {
+            let names = named.named.iter().map(|f| &f.ident);
+            quote!({ #(#names: #values),* })
+        }
+        Fields::Unnamed(_) => quote!(( #(#values),* )),
+        Fields::Unit => quote!(),
+    };
+    Ok(FieldsCode {
+        bits: quote!(#(#bits)+*),
+        pack: quote!(#(#pack)*),
+        unpack,
+    })
+}
+
+/// Expressions for each field of `self`.
+fn self_access(fields: &Fields) -> Vec<TokenStream2> {
+    fields
+        .iter()
+        .enumerate()
+        .map(|(i, field)| match &field.ident {
+            Some(ident) => quote!(&self.#ident),
+            None => {
+                let index = Index::from(i);
+                quote!(&self.#index)
+            }
+        })
+        .collect()
+}
+
+/// Bindings for each field of an enum variant.
+fn variant_bindings(fields: &Fields) -> Vec<syn::Ident> {
+    fields
+        .iter()
+        .enumerate()
+        .map(|(i, field)| match &field.ident {
+            Some(ident) => ident.clone(),
+            None => format_ident!("field{}", i),
+        })
+        .collect()
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    // Like the std derives, require the trait of every type parameter.
+    let mut generics = input.generics.clone();
+    for param in generics.type_params_mut() {
+        param.bounds.push(parse_quote!(crate::bitpack::BitPack));
+    }
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let (bits, pack, unpack) = match &input.data {
+        Data::Struct(data) => {
+            let code = fields_code(&data.fields, &self_access(&data.fields))?;
+            let FieldsCode { bits, pack, unpack } = code;
+            (bits, pack, quote!(Self #unpack))
+        }
+        Data::Enum(data) => {
+            if data.variants.is_empty() {
+                return Err(Error::new_spanned(
+                    name,
+                    "BitPack can't be derived for empty enums",
+                ));
+            }
+            let tag_bits =
+                usize::BITS as usize - (data.variants.len() - 1).leading_zeros() as usize;
+            let mut sizes = Vec::new();
+            let mut pack_arms = Vec::new();
+            let mut unpack_arms = Vec::new();
+            for (tag, variant) in data.variants.iter().enumerate() {
+                let tag = tag as u32;
+                let ident = &variant.ident;
+                let bindings = variant_bindings(&variant.fields);
+                let access: Vec<_> = bindings.iter().map(|b| quote!(#b)).collect();
+                let FieldsCode { bits, pack, unpack } = fields_code(&variant.fields, &access)?;
+                let pattern = match &variant.fields {
+                    Fields::Named(_) => quote!({ #(#bindings),* }),
+                    Fields::Unnamed(_) => quote!(( #(#bindings),* )),
+                    Fields::Unit => quote!(),
+                };
+                pack_arms.push(quote! {
+                    Self::#ident #pattern => {
+                        writer.write(#tag, #tag_bits);
+                        #pack
+                        writer.skip(<Self as crate::bitpack::BitPack>::BITS - #tag_bits - (#bits));
+                    }
+                });
+                // Unknown tags decode as the first variant.
+                let arm = if tag == 0 { quote!(_) } else { quote!(#tag) };
+                unpack_arms.push(quote! {
+                    #arm => {
+                        let value = Self::#ident #unpack;
+                        reader.skip(<Self as crate::bitpack::BitPack>::BITS - #tag_bits - (#bits));
+                        value
+                    }
+                });
+                sizes.push(bits);
+            }
+            // The catch-all arm has to come last.
+            unpack_arms.rotate_left(1);
+            let max = sizes.iter().fold(
+                quote!(0usize),
+                |acc, size| quote!(crate::bitpack::max(#acc, #size)),
+            );
+            (
+                quote!(#tag_bits + #max),
+                quote!(match self { #(#pack_arms)* }),
+                quote! {
+                    match reader.read(#tag_bits) {
+                        #(#unpack_arms)*
+                    }
+                },
+            )
+        }
+        Data::Union(_) => {
+            return Err(Error::new(
+                Span::call_site(),
+                "BitPack can't be derived for unions",
+            ))
+        }
+    };
+
+    // A non-generic layout has a known size, so check it fits persistent memory.
+    let size_check = if input.generics.params.is_empty() {
+        let prefix = format!("the BitPack layout of `{}` needs ", name);
+        quote! {
+            const _: () = {
+                let bits = <#name as crate::bitpack::BitPack>::BITS;
+                if bits > crate::bitpack::PMEM_BITS {
+                    let message = crate::bitpack::ConstMessage::new()
+                        .push_str(#prefix)
+                        .push_number(bits)
+                        .push_str(" bits, persistent memory has ")
+                        .push_number(crate::bitpack::PMEM_BITS);
+                    panic!("{}", message.as_str());
+                }
+            };
+        }
+    } else {
+        quote!()
+    };
+
+    Ok(quote! {
+        impl #impl_generics crate::bitpack::BitPack for #name #ty_generics #where_clause {
+            const BITS: usize = #bits;
+
+            #[allow(unused_variables)]
+            fn pack(&self, writer: &mut crate::bitpack::BitWriter) {
+                #pack
+            }
+
+            #[allow(unused_variables)]
+            fn unpack(reader: &mut crate::bitpack::BitReader) -> Self {
+                #unpack
+            }
+        }
+
+        #size_check
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn derive(input: TokenStream2) -> syn::Result<TokenStream2> {
+        expand(syn::parse2(input).unwrap())
+    }
+
+    #[test]
+    fn accepts_bits_within_the_type() {
+        assert!(derive(quote!(
+            struct S {
+                #[bits(8)]
+                a: u8,
+                #[bits(5)]
+                b: i16,
+            }
+        ))
+        .is_ok());
+    }
+
+    #[test]
+    fn rejects_bits_wider_than_the_type() {
+        let error = derive(quote!(
+            struct S {
+                #[bits(9)]
+                a: u8,
+            }
+        ))
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "#[bits(9)] is wider than the 8 bits of the field"
+        );
+        assert!(derive(quote!(
+            struct S(#[bits(17)] i16);
+        ))
+        .is_err());
+        assert!(derive(quote!(
+            enum E {
+                A {
+                    #[bits(33)]
+                    a: u32,
+                },
+            }
+        ))
+        .is_err());
+    }
+
+    #[test]
+    fn rejects_bad_attributes() {
+        assert!(derive(quote!(
+            struct S {
+                #[bits(0)]
+                a: u8,
+            }
+        ))
+        .is_err());
+        assert!(derive(quote!(
+            struct S {
+                #[bits]
+                a: u8,
+            }
+        ))
+        .is_err());
+        assert!(derive(quote!(
+            enum E {}
+        ))
+        .is_err());
+    }
+}
@@ -0,0 +1,410 @@
+use heapless::Vec as Vector;
+
+use crate::bitpack::BitPack;
+use crate::clip::{push_clip, ClipRect};
+use crate::mouse::{Mouse, MouseButton};
+use crate::tic80::*;
+
+/// Bindings each action can have.
+pub const MAX_BINDINGS: usize = 4;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Action {
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    CastSpell,
+    OpenInventory,
+    Pause,
+}
+
+impl Action {
+    pub const ALL: [Action; ACTION_COUNT] = [
+        Action::MoveUp,
+        Action::MoveDown,
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::CastSpell,
+        Action::OpenInventory,
+        Action::Pause,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::MoveUp => "MOVE UP",
+            Action::MoveDown => "MOVE DOWN",
+            Action::MoveLeft => "MOVE LEFT",
+            Action::MoveRight => "MOVE RIGHT",
+            Action::CastSpell => "CAST SPELL",
+            Action::OpenInventory => "INVENTORY",
+            Action::Pause => "PAUSE",
+        }
+    }
+}
+
+pub const ACTION_COUNT: usize = 7;
+
+/// An input that can trigger an action.
+#[derive(BitPack, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Binding {
+    /// Gamepad button id as `btn` takes it, 8 per player.
+    Gamepad(#[bits(5)] u8),
+    /// Keycode as `key` takes it.
+    Key(#[bits(7)] u8),
+    Mouse(MouseButton),
+}
+
+const BUTTON_NAMES: [&str; 8] = ["UP", "DOWN", "LEFT", "RIGHT", "A", "B", "X", "Y"];
+/// Names of the keys after the letters and digits, from `KEY_MINUS` on.
+const OTHER_KEY_NAMES: &str = "- = [ ] \\ ; ' ` , . / SPACE TAB RETURN BACKSPACE DELETE INSERT \
+    PAGEUP PAGEDOWN HOME END UP DOWN LEFT RIGHT CAPSLOCK CTRL SHIFT ALT";
+
+fn key_name(code: i32) -> String {
+    match code {
+        KEY_A..=KEY_Z => char::from(b'A' + (code - KEY_A) as u8).to_string(),
+        KEY_0..=KEY_9 => char::from(b'0' + (code - KEY_0) as u8).to_string(),
+        _ => OTHER_KEY_NAMES
+            .split_whitespace()
+            .nth((code - KEY_MINUS).max(0) as usize)
+            .unwrap_or("?")
+            .to_string(),
+    }
+}
+
+impl Binding {
+    /// Whether the input is held this frame.
+    pub fn down(self, mouse: &Mouse) -> bool {
+        match self {
+            Binding::Gamepad(id) => btn(id.into()),
+            Binding::Key(code) => key(code.into()),
+            Binding::Mouse(button) => mouse.down(button),
+        }
+    }
+
+    /// A short label for menus, like `P1 A`, `KEY W` or `MOUSE LEFT`.
+    pub fn name(self) -> String {
+        match self {
+            Binding::Gamepad(id) => {
+                format!("P{} {}", id / 8 + 1, BUTTON_NAMES[(id % 8) as usize])
+            }
+            Binding::Key(code) => format!("KEY {}", key_name(code.into())),
+            Binding::Mouse(button) => format!("MOUSE {:?}", button).to_uppercase(),
+        }
+    }
+}
+
+/// The bindings of every action, stored in persistent memory.
+#[derive(BitPack, Clone, PartialEq, Eq, Debug)]
+pub struct Bindings {
+    actions: [Vector<Binding, MAX_BINDINGS>; ACTION_COUNT],
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        let defaults: [&[Binding]; ACTION_COUNT] = [
+            &[Binding::Gamepad(0), Binding::Key(KEY_W as u8)],
+            &[Binding::Gamepad(1), Binding::Key(KEY_S as u8)],
+            &[Binding::Gamepad(2), Binding::Key(KEY_A as u8)],
+            &[Binding::Gamepad(3), Binding::Key(KEY_D as u8)],
+            // The left mouse button draws runes.
+            &[Binding::Gamepad(4), Binding::Key(KEY_SPACE as u8)],
+            &[Binding::Gamepad(6), Binding::Key(KEY_I as u8)],
+            &[Binding::Gamepad(7), Binding::Key(KEY_P as u8)],
+        ];
+        Self {
+            actions: defaults.map(|bindings| Vector::from_slice(bindings).unwrap()),
+        }
+    }
+}
+
+impl Bindings {
+    pub fn get(&self, action: Action) -> &[Binding] {
+        &self.actions[action as usize]
+    }
+
+    /// Add `binding` to `action`, replacing its last binding when full.
+    pub fn add(&mut self, action: Action, binding: Binding) {
+        let bindings = &mut self.actions[action as usize];
+        if bindings.contains(&binding) {
+            return;
+        }
+        if bindings.is_full() {
+            bindings[MAX_BINDINGS - 1] = binding;
+        } else {
+            // Okay to unwrap, there's room.
+            bindings.push(binding).unwrap();
+        }
+    }
+
+    pub fn clear(&mut self, action: Action) {
+        self.actions[action as usize].clear();
+    }
+
+    /// Put back the default bindings of `action`.
+    pub fn reset(&mut self, action: Action) {
+        self.actions[action as usize] = Bindings::default().actions[action as usize].clone();
+    }
+}
+
+/// Whether an input held for `held` frames repeats this frame, see
+/// [`Actions::repeat`]. Unlike `btnp` and `keyp`, which count from the live
+/// input, this follows whatever is in the input RAM, so replays repeat the
+/// same way.
+pub fn repeats(held: u32, hold: u32, period: u32) -> bool {
+    held == 1 || (held > hold && period > 0 && (held - hold - 1).is_multiple_of(period))
+}
+
+/// Game actions read through rebindable [`Bindings`]. Call
+/// [`Actions::update`] once per frame before querying.
+#[derive(Clone, Debug, Default)]
+pub struct Actions {
+    bindings: Bindings,
+    mouse: Mouse,
+    /// A bit per action.
+    down: u32,
+    previous: u32,
+    held_frames: [u32; ACTION_COUNT],
+}
+
+impl Actions {
+    pub fn new(bindings: Bindings) -> Self {
+        Self {
+            bindings,
+            ..Default::default()
+        }
+    }
+
+    pub fn bindings(&self) -> &Bindings {
+        &self.bindings
+    }
+
+    pub fn bindings_mut(&mut self) -> &mut Bindings {
+        &mut self.bindings
+    }
+
+    pub fn mouse(&self) -> &Mouse {
+        &self.mouse
+    }
+
+    pub fn mouse_mut(&mut self) -> &mut Mouse {
+        &mut self.mouse
+    }
+
+    pub fn update(&mut self) {
+        self.mouse.update();
+        self.previous = self.down;
+        self.down = 0;
+        for action in Action::ALL {
+            let index = action as usize;
+            let down = self.bindings.actions[index]
+                .iter()
+                .any(|binding| binding.down(&self.mouse));
+            if down {
+                self.down |= 1 << index;
+                self.held_frames[index] += 1;
+            } else {
+                self.held_frames[index] = 0;
+            }
+        }
+    }
+
+    pub fn down(&self, action: Action) -> bool {
+        self.down & (1 << action as usize) != 0
+    }
+
+    pub fn just_pressed(&self, action: Action) -> bool {
+        self.down(action) && self.previous & (1 << action as usize) == 0
+    }
+
+    pub fn just_released(&self, action: Action) -> bool {
+        !self.down(action) && self.previous & (1 << action as usize) != 0
+    }
+
+    /// Frames the action has been held, 0 when it's up.
+    pub fn held_frames(&self, action: Action) -> u32 {
+        self.held_frames[action as usize]
+    }
+
+    /// Like `btnp` with `hold` and `period`: true when pressed, then every
+    /// `period` frames once held for `hold` frames.
+    pub fn repeat(&self, action: Action, hold: u32, period: u32) -> bool {
+        repeats(self.held_frames(action), hold, period)
+    }
+}
+
+/// The options screen for rebinding actions. Up and down pick an action,
+/// cast adds an input to it (the next button, key or mouse button pressed),
+/// inventory resets it to the defaults and pause closes the menu.
+#[derive(Clone, Debug, Default)]
+pub struct RebindMenu {
+    cursor: usize,
+    listening: bool,
+    gamepad: u32,
+    keys: u128,
+    mouse: u8,
+}
+
+impl RebindMenu {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Handle input, returns `true` when the menu is closed.
+    pub fn update(&mut self, actions: &mut Actions) -> bool {
+        // Newly pressed inputs since last frame, for capturing a binding.
+        let gamepad = btn_bits() as u32;
+        let keys = (1..=KEY_COUNT)
+            .filter(|&code| key(code))
+            .fold(0u128, |bits, code| bits | 1 << code);
+        let mouse = MouseButton::ALL
+            .iter()
+            .enumerate()
+            .filter(|(_, &button)| actions.mouse().down(button))
+            .fold(0u8, |bits, (i, _)| bits | 1 << i);
+        let new_gamepad = gamepad & !self.gamepad;
+        let new_keys = keys & !self.keys;
+        let new_mouse = mouse & !self.mouse;
+        self.gamepad = gamepad;
+        self.keys = keys;
+        self.mouse = mouse;
+
+        let action = Action::ALL[self.cursor];
+        if self.listening {
+            let captured = if new_gamepad != 0 {
+                Some(Binding::Gamepad(new_gamepad.trailing_zeros() as u8))
+            } else if new_keys != 0 {
+                Some(Binding::Key(new_keys.trailing_zeros() as u8))
+            } else if new_mouse != 0 {
+                Some(Binding::Mouse(
+                    MouseButton::ALL[new_mouse.trailing_zeros() as usize],
+                ))
+            } else {
+                None
+            };
+            if let Some(binding) = captured {
+                actions.bindings_mut().add(action, binding);
+                self.listening = false;
+            }
+            return false;
+        }
+
+        if actions.repeat(Action::MoveUp, 15, 6) {
+            self.cursor = (self.cursor + ACTION_COUNT - 1) % ACTION_COUNT;
+        }
+        if actions.repeat(Action::MoveDown, 15, 6) {
+            self.cursor = (self.cursor + 1) % ACTION_COUNT;
+        }
+        if actions.just_pressed(Action::CastSpell) {
+            self.listening = true;
+        } else if actions.just_pressed(Action::OpenInventory) {
+            actions.bindings_mut().reset(action);
+        } else if actions.just_pressed(Action::Pause) {
+            return true;
+        }
+        false
+    }
+
+    pub fn draw(&self, actions: &Actions) {
+        cls(0);
+        Print::default().x(8).y(6).color(12).print("CONTROLS\0");
+        for (i, &action) in Action::ALL.iter().enumerate() {
+            let y = 20 + i as i32 * 14;
+            if i == self.cursor {
+                rect(4, y - 3, 232, 12, 8);
+            }
+            Print::default()
+                .x(8)
+                .y(y)
+                .color(12)
+                .print(format!("{}\0", action.name()));
+            let bindings = if i == self.cursor && self.listening {
+                "PRESS AN INPUT...".to_string()
+            } else {
+                actions
+                    .bindings()
+                    .get(action)
+                    .iter()
+                    .map(|binding| binding.name())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            };
+            // Long binding lists are cut at the edge of the row.
+            let _row = push_clip(ClipRect::new(76, y - 3, 156, 12));
+            Print::default()
+                .x(76)
+                .y(y)
+                .color(13)
+                .smallfont(true)
+                .print(format!("{}\0", bindings));
+        }
+        Print::default()
+            .x(8)
+            .y(124)
+            .color(13)
+            .smallfont(true)
+            .print("CAST: ADD INPUT   INVENTORY: RESET   PAUSE: BACK\0");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_bindings() {
+        assert_eq!(Binding::Gamepad(4).name(), "P1 A");
+        assert_eq!(Binding::Gamepad(15).name(), "P2 Y");
+        assert_eq!(Binding::Key(KEY_W as u8).name(), "KEY W");
+        assert_eq!(Binding::Key(KEY_0 as u8).name(), "KEY 0");
+        assert_eq!(Binding::Key(KEY_SPACE as u8).name(), "KEY SPACE");
+        assert_eq!(Binding::Key(KEY_ALT as u8).name(), "KEY ALT");
+        assert_eq!(Binding::Mouse(MouseButton::Right).name(), "MOUSE RIGHT");
+    }
+
+    #[test]
+    fn adding_replaces_the_last_binding_when_full() {
+        let mut bindings = Bindings::default();
+        bindings.clear(Action::Pause);
+        for id in 0..MAX_BINDINGS as u8 + 1 {
+            bindings.add(Action::Pause, Binding::Gamepad(id));
+        }
+        bindings.add(Action::Pause, Binding::Gamepad(0));
+        assert_eq!(
+            bindings.get(Action::Pause),
+            &[
+                Binding::Gamepad(0),
+                Binding::Gamepad(1),
+                Binding::Gamepad(2),
+                Binding::Gamepad(4)
+            ]
+        );
+        bindings.reset(Action::Pause);
+        assert_eq!(bindings, Bindings::default());
+    }
+
+    #[test]
+    fn bindings_round_trip() {
+        let mut bindings = Bindings::default();
+        bindings.add(Action::CastSpell, Binding::Mouse(MouseButton::Middle));
+        bindings.clear(Action::MoveUp);
+        let mut words = [0; 16];
+        crate::bitpack::pack_words(&bindings, &mut words).unwrap();
+        let unpacked: Bindings = crate::bitpack::unpack_words(&words).unwrap();
+        assert_eq!(unpacked, bindings);
+    }
+
+    #[test]
+    fn repeats_like_btnp() {
+        let mut actions = Actions::default();
+        let fired: Vec<u32> = (1..=40)
+            .filter(|&held| {
+                actions.held_frames[0] = held;
+                actions.repeat(Action::MoveUp, 15, 6)
+            })
+            .collect();
+        assert_eq!(fired, [1, 16, 22, 28, 34, 40]);
+        actions.held_frames[0] = 0;
+        assert!(!actions.repeat(Action::MoveUp, 15, 6));
+    }
+}
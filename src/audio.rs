@@ -0,0 +1,397 @@
+use heapless::Vec as Vector;
+
+use crate::tic80::*;
+use crate::tic80_error::Tic80Error;
+
+pub const CHANNELS: usize = 4;
+const MAX_VOLUME: f32 = 15.0;
+
+#[derive(Clone, Copy, Default, Debug)]
+struct ChannelSlot {
+    id: i32,
+    priority: u8,
+    /// Frame the sound stops, 0 when free.
+    ends_at: u32,
+    started_at: u32,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Cooldown {
+    id: i32,
+    frames: u32,
+    last: Option<u32>,
+}
+
+/// Linear change of a volume towards `target` over a number of frames.
+#[derive(Clone, Copy, Debug)]
+struct Fade {
+    target: f32,
+    step: f32,
+}
+
+impl Fade {
+    fn new(from: f32, target: f32, frames: u32) -> Self {
+        Self {
+            target,
+            step: (target - from).abs() / frames.max(1) as f32,
+        }
+    }
+
+    /// Move `value` one frame along, returns `true` once the target is reached.
+    fn advance(&self, value: &mut f32) -> bool {
+        if (*value - self.target).abs() <= self.step {
+            *value = self.target;
+            true
+        } else {
+            *value += self.step.copysign(self.target - *value);
+            false
+        }
+    }
+}
+
+/// Shares the four sound channels between sound effects and music.
+/// Effects get a free channel or take one from a lower priority effect,
+/// channels can be reserved for music, and `STEREO_VOLUME` is written from
+/// the master, music and per-channel volumes every [`AudioManager::update`].
+pub struct AudioManager {
+    frame: u32,
+    slots: [ChannelSlot; CHANNELS],
+    music_channels: u8,
+    cooldowns: Vector<Cooldown, 32>,
+    master: f32,
+    music_volume: f32,
+    music_fade: Option<Fade>,
+    duck: f32,
+    duck_fade: Option<Fade>,
+    stereo: [(f32, f32); CHANNELS],
+}
+
+impl Default for AudioManager {
+    fn default() -> Self {
+        Self {
+            frame: 0,
+            slots: [ChannelSlot::default(); CHANNELS],
+            music_channels: 0,
+            cooldowns: Vector::new(),
+            master: 1.0,
+            music_volume: 1.0,
+            music_fade: None,
+            duck: 1.0,
+            duck_fade: None,
+            stereo: [(1.0, 1.0); CHANNELS],
+        }
+    }
+}
+
+impl AudioManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep `channels` (a bit per channel) for music, effects won't use them.
+    pub fn reserve_music_channels(&mut self, channels: u8) -> &mut Self {
+        self.music_channels = channels & 0x0f;
+        self
+    }
+
+    /// Don't play effect `id` again until `frames` frames after it last
+    /// started. At most 32 effects can have an interval.
+    pub fn min_interval(&mut self, id: i32, frames: u32) -> Result<&mut Self, Tic80Error> {
+        if let Some(cooldown) = self.cooldowns.iter_mut().find(|c| c.id == id) {
+            cooldown.frames = frames;
+        } else {
+            let len = self.cooldowns.capacity();
+            self.cooldowns
+                .push(Cooldown {
+                    id,
+                    frames,
+                    last: None,
+                })
+                .map_err(|_| Tic80Error::OutOfRange { index: len, len })?;
+        }
+        Ok(self)
+    }
+
+    fn is_music_channel(&self, channel: usize) -> bool {
+        self.music_channels & (1 << channel) != 0
+    }
+
+    /// Pick a channel for a sound of `priority`, `None` when every usable
+    /// channel is busy with something at least as important.
+    pub fn allocate(&self, priority: u8) -> Option<usize> {
+        let usable = (0..CHANNELS).filter(|&c| !self.is_music_channel(c));
+        let mut best: Option<usize> = None;
+        for channel in usable {
+            let slot = &self.slots[channel];
+            if slot.ends_at <= self.frame {
+                return Some(channel);
+            }
+            if slot.priority < priority {
+                // Replace the least important sound, the oldest on a tie.
+                let better = best.is_none_or(|b| {
+                    let current = &self.slots[b];
+                    (slot.priority, slot.started_at) < (current.priority, current.started_at)
+                });
+                if better {
+                    best = Some(channel);
+                }
+            }
+        }
+        best
+    }
+
+    /// Play effect `id` for `duration` frames, -1 plays until stopped. The
+    /// channel and duration of `sfx` are set here, other options are kept.
+    /// Returns the channel used, or `None` when the sound was dropped.
+    pub fn play(&mut self, id: i32, priority: u8, duration: i32, mut sfx: Sfx) -> Option<usize> {
+        let channel = self.claim(id, priority, duration)?;
+        sfx.channel(channel as i32).duration(duration);
+        sfx.sfx(id);
+        Some(channel)
+    }
+
+    /// The bookkeeping of [`AudioManager::play`]. The cooldown only starts
+    /// when a channel was found, so a dropped sound can retrigger at once.
+    fn claim(&mut self, id: i32, priority: u8, duration: i32) -> Option<usize> {
+        let frame = self.frame;
+        let cooling = self
+            .cooldowns
+            .iter()
+            .find(|c| c.id == id)
+            .and_then(|c| c.last.map(|last| frame < last.saturating_add(c.frames)));
+        if cooling == Some(true) {
+            return None;
+        }
+        let channel = self.allocate(priority)?;
+        if let Some(cooldown) = self.cooldowns.iter_mut().find(|c| c.id == id) {
+            cooldown.last = Some(frame);
+        }
+        self.slots[channel] = ChannelSlot {
+            id,
+            priority,
+            ends_at: if duration < 0 {
+                u32::MAX
+            } else {
+                frame.saturating_add(duration.max(1) as u32)
+            },
+            started_at: frame,
+        };
+        Some(channel)
+    }
+
+    /// Stop whatever effect is on `channel`.
+    pub fn stop(&mut self, channel: usize) {
+        if channel < CHANNELS && !self.is_music_channel(channel) {
+            self.slots[channel] = ChannelSlot::default();
+            let mut sfx = Sfx::default();
+            sfx.channel(channel as i32);
+            sfx.sfx(-1);
+        }
+    }
+
+    /// Returns the effect playing on `channel`, by this manager's bookkeeping.
+    pub fn playing(&self, channel: usize) -> Option<i32> {
+        self.slots
+            .get(channel)
+            .filter(|s| s.ends_at > self.frame)
+            .map(|s| s.id)
+    }
+
+    /// Master volume 0.0..=1.0 for all channels.
+    pub fn set_master_volume(&mut self, volume: f32) {
+        self.master = volume.clamp(0.0, 1.0);
+    }
+
+    pub fn master_volume(&self) -> f32 {
+        self.master
+    }
+
+    /// Set the volume 0.0..=1.0 of the music channels.
+    pub fn set_music_volume(&mut self, volume: f32) {
+        self.music_volume = volume.clamp(0.0, 1.0);
+        self.music_fade = None;
+    }
+
+    pub fn music_volume(&self) -> f32 {
+        self.music_volume
+    }
+
+    /// Fade the music channels to `volume` over `frames` frames.
+    pub fn fade_music(&mut self, volume: f32, frames: u32) {
+        let target = volume.clamp(0.0, 1.0);
+        self.music_fade = Some(Fade::new(self.music_volume, target, frames));
+    }
+
+    /// Lower the music to `level` of its volume, e.g. under dialogue.
+    pub fn duck(&mut self, level: f32, frames: u32) {
+        self.duck_fade = Some(Fade::new(self.duck, level.clamp(0.0, 1.0), frames));
+    }
+
+    /// Bring the music back up after [`AudioManager::duck`].
+    pub fn unduck(&mut self, frames: u32) {
+        self.duck_fade = Some(Fade::new(self.duck, 1.0, frames));
+    }
+
+    /// Left and right volume 0.0..=1.0 of one channel, before master volume.
+    pub fn set_channel_stereo(&mut self, channel: usize, left: f32, right: f32) {
+        if let Some(stereo) = self.stereo.get_mut(channel) {
+            *stereo = (left.clamp(0.0, 1.0), right.clamp(0.0, 1.0));
+        }
+    }
+
+    pub fn channel_stereo(&self, channel: usize) -> (f32, f32) {
+        self.stereo.get(channel).copied().unwrap_or((0.0, 0.0))
+    }
+
+    /// Advance fades one frame and write `STEREO_VOLUME`. Call once per frame.
+    pub fn update(&mut self) {
+        self.frame += 1;
+        if let Some(fade) = self.music_fade {
+            if fade.advance(&mut self.music_volume) {
+                self.music_fade = None;
+            }
+        }
+        if let Some(fade) = self.duck_fade {
+            if fade.advance(&mut self.duck) {
+                self.duck_fade = None;
+            }
+        }
+        for channel in 0..CHANNELS {
+            let (left, right) = self.stereo[channel];
+            let mut gain = self.master;
+            if self.is_music_channel(channel) {
+                gain *= self.music_volume * self.duck;
+            }
+            write_stereo_volume(channel, left * gain, right * gain);
+        }
+    }
+}
+
+/// Write one channel of `STEREO_VOLUME`, left in the low nibble and right
+/// in the high nibble, from volumes 0.0..=1.0.
+pub fn write_stereo_volume(channel: usize, left: f32, right: f32) {
+    if channel >= CHANNELS {
+        return;
+    }
+    unsafe {
+        let stereo = &mut *STEREO_VOLUME;
+        stereo[channel] = stereo_byte(left, right);
+    }
+}
+
+fn stereo_byte(left: f32, right: f32) -> u8 {
+    let left = (left.clamp(0.0, 1.0) * MAX_VOLUME + 0.5) as u8;
+    let right = (right.clamp(0.0, 1.0) * MAX_VOLUME + 0.5) as u8;
+    left | (right << 4)
+}
+
+/// Returns `true` while a sound effect plays on `channel`. `SFX_STATE` holds
+/// four envelope positions per channel, which are -1 when it's idle.
+pub fn sfx_channel_playing(channel: usize) -> bool {
+    if channel >= CHANNELS {
+        return false;
+    }
+    let state = unsafe { *SFX_STATE };
+    (state[channel * 4] as i8) >= 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn free_channels_first_then_lower_priority() {
+        let mut audio = AudioManager::new();
+        for id in 0..4 {
+            assert_eq!(audio.claim(id, 1, 10), Some(id as usize));
+        }
+        // Everything is busy with priority 1.
+        assert_eq!(audio.claim(10, 1, 10), None);
+        assert_eq!(audio.claim(10, 2, 10), Some(0));
+        // Now channel 0 has priority 2, the oldest priority 1 sound goes.
+        assert_eq!(audio.claim(11, 2, 10), Some(1));
+        assert_eq!(audio.playing(1), Some(11));
+    }
+
+    #[test]
+    fn music_channels_are_skipped() {
+        let mut audio = AudioManager::new();
+        audio.reserve_music_channels(0b0111);
+        assert_eq!(audio.claim(1, 0, 10), Some(3));
+        assert_eq!(audio.claim(2, 9, 10), Some(3));
+        audio.reserve_music_channels(0b1111);
+        assert_eq!(audio.claim(3, 9, 10), None);
+    }
+
+    #[test]
+    fn channels_free_up_after_the_duration() {
+        let mut audio = AudioManager::new();
+        audio.reserve_music_channels(0b1110);
+        audio.claim(1, 5, 2);
+        audio.frame += 1;
+        assert_eq!(audio.playing(0), Some(1));
+        audio.frame += 1;
+        assert_eq!(audio.playing(0), None);
+        assert_eq!(audio.claim(2, 0, 2), Some(0));
+    }
+
+    #[test]
+    fn negative_durations_play_until_stopped() {
+        let mut audio = AudioManager::new();
+        audio.reserve_music_channels(0b1110);
+        audio.claim(1, 0, -1);
+        audio.frame = u32::MAX - 1;
+        assert_eq!(audio.playing(0), Some(1));
+    }
+
+    #[test]
+    fn cooldown_blocks_retriggers() {
+        let mut audio = AudioManager::new();
+        audio.min_interval(7, 5).unwrap();
+        assert!(audio.claim(7, 0, 1).is_some());
+        audio.frame += 4;
+        assert!(audio.claim(7, 0, 1).is_none());
+        audio.frame += 1;
+        assert!(audio.claim(7, 0, 1).is_some());
+    }
+
+    #[test]
+    fn dropped_sounds_dont_start_the_cooldown() {
+        let mut audio = AudioManager::new();
+        audio.reserve_music_channels(0b1110);
+        audio.min_interval(7, 30).unwrap();
+        audio.claim(1, 9, 10);
+        assert!(audio.claim(7, 0, 1).is_none());
+        audio.slots[0] = ChannelSlot::default();
+        assert!(audio.claim(7, 0, 1).is_some());
+    }
+
+    #[test]
+    fn intervals_past_capacity_are_an_error() {
+        let mut audio = AudioManager::new();
+        for id in 0..32 {
+            audio.min_interval(id, 1).unwrap();
+        }
+        // Changing an existing one still works.
+        assert!(audio.min_interval(0, 2).is_ok());
+        assert!(audio.min_interval(32, 1).is_err());
+    }
+
+    #[test]
+    fn fades_reach_the_target() {
+        let fade = Fade::new(1.0, 0.0, 4);
+        let mut volume = 1.0;
+        for _ in 0..3 {
+            assert!(!fade.advance(&mut volume));
+        }
+        assert!(fade.advance(&mut volume));
+        assert_eq!(volume, 0.0);
+    }
+
+    #[test]
+    fn stereo_volume_nibbles() {
+        assert_eq!(stereo_byte(1.0, 0.0), 0x0f);
+        assert_eq!(stereo_byte(0.0, 1.0), 0xf0);
+        assert_eq!(stereo_byte(0.5, 2.0), 0xf8);
+    }
+}
@@ -0,0 +1,309 @@
+use heapless::Vec as Vector;
+
+use crate::tic80::*;
+use crate::tic80_error::Tic80Error;
+
+// Neighbour bits for the 4-bit mask.
+pub const EDGE_N: u8 = 1;
+pub const EDGE_E: u8 = 2;
+pub const EDGE_S: u8 = 4;
+pub const EDGE_W: u8 = 8;
+
+// Neighbour bits for the 8-bit mask.
+pub const BLOB_NW: u8 = 1;
+pub const BLOB_N: u8 = 2;
+pub const BLOB_NE: u8 = 4;
+pub const BLOB_W: u8 = 8;
+pub const BLOB_E: u8 = 16;
+pub const BLOB_SW: u8 = 32;
+pub const BLOB_S: u8 = 64;
+pub const BLOB_SE: u8 = 128;
+
+/// Terrain ids are `0..TERRAINS`, one bit each in [`TerrainRule::connects`].
+pub const TERRAINS: u8 = 16;
+
+/// Number of distinct tiles in an 8-bit (blob) tile set.
+pub const BLOB_TILES: usize = 47;
+
+/// Drop the corner bits that don't have both adjacent edges set, a corner
+/// only matters for picking an inner corner tile.
+pub const fn reduce_blob(mask: u8) -> u8 {
+    let mut m = mask;
+    if m & BLOB_N == 0 || m & BLOB_W == 0 {
+        m &= !BLOB_NW;
+    }
+    if m & BLOB_N == 0 || m & BLOB_E == 0 {
+        m &= !BLOB_NE;
+    }
+    if m & BLOB_S == 0 || m & BLOB_W == 0 {
+        m &= !BLOB_SW;
+    }
+    if m & BLOB_S == 0 || m & BLOB_E == 0 {
+        m &= !BLOB_SE;
+    }
+    m
+}
+
+const fn blob_table() -> [u8; 256] {
+    let mut table = [0u8; 256];
+    let mut next = 0u8;
+    let mut mask = 0;
+    while mask < 256 {
+        if reduce_blob(mask as u8) == mask as u8 {
+            table[mask] = next;
+            next += 1;
+        }
+        mask += 1;
+    }
+    mask = 0;
+    while mask < 256 {
+        table[mask] = table[reduce_blob(mask as u8) as usize];
+        mask += 1;
+    }
+    table
+}
+
+/// Maps any 8-bit mask to a blob tile index in `0..BLOB_TILES`, ordered by
+/// the value of the reduced mask.
+pub const BLOB_INDEX: [u8; 256] = blob_table();
+
+/// Which neighbours decide the tile.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MaskKind {
+    /// 16 tiles at `base_tile + mask`, using the `EDGE_*` bits.
+    Edges4,
+    /// 47 tiles at `base_tile + BLOB_INDEX[mask]`, using the `BLOB_*` bits.
+    Blob8,
+}
+
+/// How one terrain type is turned into tiles.
+#[derive(Clone, Copy, Debug)]
+pub struct TerrainRule {
+    pub terrain: u8,
+    pub mask: MaskKind,
+    pub base_tile: i32,
+    /// Bit set of the terrains this one joins up with, itself included.
+    pub connects: u16,
+}
+
+fn check_terrain(terrain: u8) -> Result<(), Tic80Error> {
+    if terrain >= TERRAINS {
+        return Err(Tic80Error::OutOfRange {
+            index: terrain as usize,
+            len: TERRAINS as usize,
+        });
+    }
+    Ok(())
+}
+
+impl TerrainRule {
+    pub fn new(terrain: u8, mask: MaskKind, base_tile: i32) -> Result<Self, Tic80Error> {
+        check_terrain(terrain)?;
+        Ok(Self {
+            terrain,
+            mask,
+            base_tile,
+            connects: 1 << terrain,
+        })
+    }
+
+    /// Also join up with `terrain`.
+    pub fn connects_to(mut self, terrain: u8) -> Result<Self, Tic80Error> {
+        check_terrain(terrain)?;
+        self.connects |= 1 << terrain;
+        Ok(self)
+    }
+
+    fn joins(&self, terrain: u8) -> bool {
+        1u16.checked_shl(terrain as u32)
+            .is_some_and(|bit| self.connects & bit != 0)
+    }
+}
+
+/// A logical grid of terrain ids (0..16) placed on the MAP at `origin_x`,
+/// `origin_y`, retiled with `mset` as cells change.
+pub struct Autotiler<const W: usize, const H: usize> {
+    pub origin_x: i32,
+    pub origin_y: i32,
+    cells: [[u8; W]; H],
+    rules: Vector<TerrainRule, 8>,
+}
+
+impl<const W: usize, const H: usize> Autotiler<W, H> {
+    pub fn new(origin_x: i32, origin_y: i32) -> Self {
+        Self {
+            origin_x,
+            origin_y,
+            cells: [[0; W]; H],
+            rules: Vector::new(),
+        }
+    }
+
+    /// Add a rule, at most 8.
+    pub fn with_rule(mut self, rule: TerrainRule) -> Result<Self, Tic80Error> {
+        let len = self.rules.capacity();
+        self.rules
+            .push(rule)
+            .map_err(|_| Tic80Error::OutOfRange { index: len, len })?;
+        Ok(self)
+    }
+
+    /// Returns the terrain at grid coordinates, `None` outside the grid.
+    pub fn get(&self, x: i32, y: i32) -> Option<u8> {
+        if x < 0 || y < 0 || x >= W as i32 || y >= H as i32 {
+            return None;
+        }
+        Some(self.cells[y as usize][x as usize])
+    }
+
+    /// Change one cell and retile it and its neighbours. Cells outside the
+    /// grid are ignored.
+    pub fn set(&mut self, x: i32, y: i32, terrain: u8) -> Result<(), Tic80Error> {
+        self.fill(x, y, terrain)?;
+        for ny in y - 1..=y + 1 {
+            for nx in x - 1..=x + 1 {
+                self.retile(nx, ny);
+            }
+        }
+        Ok(())
+    }
+
+    /// Set a cell without retiling, for filling the grid before [`Autotiler::retile_all`].
+    pub fn fill(&mut self, x: i32, y: i32, terrain: u8) -> Result<(), Tic80Error> {
+        check_terrain(terrain)?;
+        if self.get(x, y).is_some() {
+            self.cells[y as usize][x as usize] = terrain;
+        }
+        Ok(())
+    }
+
+    pub fn retile_all(&self) {
+        for y in 0..H as i32 {
+            for x in 0..W as i32 {
+                self.retile(x, y);
+            }
+        }
+    }
+
+    /// Recompute the tile of one cell, cells without a rule are left alone.
+    pub fn retile(&self, x: i32, y: i32) {
+        if let Some(tile) = self.tile_for(x, y) {
+            mset(self.origin_x + x, self.origin_y + y, tile);
+        }
+    }
+
+    /// Returns the tile id the cell should show.
+    pub fn tile_for(&self, x: i32, y: i32) -> Option<i32> {
+        let terrain = self.get(x, y)?;
+        let rule = self.rules.iter().find(|r| r.terrain == terrain)?;
+        // Neighbours off the grid count as joined so edges don't show at the border.
+        let joined = |dx: i32, dy: i32| self.get(x + dx, y + dy).is_none_or(|t| rule.joins(t));
+        let tile = match rule.mask {
+            MaskKind::Edges4 => {
+                let mut mask = 0;
+                for (bit, dx, dy) in [
+                    (EDGE_N, 0, -1),
+                    (EDGE_E, 1, 0),
+                    (EDGE_S, 0, 1),
+                    (EDGE_W, -1, 0),
+                ] {
+                    if joined(dx, dy) {
+                        mask |= bit;
+                    }
+                }
+                rule.base_tile + mask as i32
+            }
+            MaskKind::Blob8 => {
+                let mut mask = 0;
+                for (bit, dx, dy) in [
+                    (BLOB_NW, -1, -1),
+                    (BLOB_N, 0, -1),
+                    (BLOB_NE, 1, -1),
+                    (BLOB_W, -1, 0),
+                    (BLOB_E, 1, 0),
+                    (BLOB_SW, -1, 1),
+                    (BLOB_S, 0, 1),
+                    (BLOB_SE, 1, 1),
+                ] {
+                    if joined(dx, dy) {
+                        mask |= bit;
+                    }
+                }
+                rule.base_tile + BLOB_INDEX[mask as usize] as i32
+            }
+        };
+        Some(tile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRASS: u8 = 1;
+    const WATER: u8 = 2;
+
+    fn tiler() -> Autotiler<4, 4> {
+        Autotiler::new(0, 0)
+            .with_rule(TerrainRule::new(GRASS, MaskKind::Edges4, 16).unwrap())
+            .unwrap()
+            .with_rule(TerrainRule::new(WATER, MaskKind::Blob8, 64).unwrap())
+            .unwrap()
+    }
+
+    #[test]
+    fn blob_index_has_47_tiles() {
+        let mut seen = [false; BLOB_TILES];
+        for mask in 0..=255u8 {
+            seen[BLOB_INDEX[mask as usize] as usize] = true;
+        }
+        assert!(seen.iter().all(|&s| s));
+        assert_eq!(BLOB_INDEX[0], 0);
+        assert_eq!(BLOB_INDEX[255], BLOB_TILES as u8 - 1);
+        // A lone corner bit reduces away.
+        assert_eq!(BLOB_INDEX[BLOB_NW as usize], 0);
+    }
+
+    #[test]
+    fn edges_follow_the_neighbours() {
+        let mut tiler = tiler();
+        for y in 0..4 {
+            for x in 0..4 {
+                tiler.fill(x, y, WATER).unwrap();
+            }
+        }
+        tiler.fill(1, 1, GRASS).unwrap();
+        tiler.fill(2, 1, GRASS).unwrap();
+        // Only the east neighbour is grass.
+        assert_eq!(tiler.tile_for(1, 1), Some(16 + EDGE_E as i32));
+        assert_eq!(tiler.tile_for(2, 1), Some(16 + EDGE_W as i32));
+        // Water everywhere else, and the grid border counts as joined.
+        assert_eq!(tiler.tile_for(3, 3), Some(64 + BLOB_TILES as i32 - 1));
+    }
+
+    #[test]
+    fn cells_without_a_rule_are_left_alone() {
+        assert_eq!(tiler().tile_for(0, 0), None);
+        assert_eq!(tiler().tile_for(4, 0), None);
+    }
+
+    #[test]
+    fn terrain_ids_past_16_are_rejected() {
+        assert!(TerrainRule::new(TERRAINS, MaskKind::Edges4, 0).is_err());
+        let rule = TerrainRule::new(GRASS, MaskKind::Edges4, 0).unwrap();
+        assert!(rule.connects_to(TERRAINS).is_err());
+        assert!(!rule.joins(200));
+        assert!(tiler().fill(0, 0, TERRAINS).is_err());
+    }
+
+    #[test]
+    fn rules_past_capacity_are_an_error() {
+        let mut tiler = Autotiler::<1, 1>::new(0, 0);
+        for terrain in 0..8 {
+            let rule = TerrainRule::new(terrain, MaskKind::Edges4, 0).unwrap();
+            tiler = tiler.with_rule(rule).unwrap();
+        }
+        let rule = TerrainRule::new(8, MaskKind::Edges4, 0).unwrap();
+        assert!(tiler.with_rule(rule).is_err());
+    }
+}
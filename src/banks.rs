@@ -0,0 +1,329 @@
+use crate::tic80::*;
+use crate::tic80_error::Tic80Error;
+
+pub const BANKS: usize = 8;
+
+fn section_index(section: SyncMask) -> usize {
+    section.bits().trailing_zeros() as usize
+}
+
+/// Switches cart sections between the eight memory banks with `sync`,
+/// keeping track of the bank each section was loaded from so edits made at
+/// runtime can be written back to it.
+///
+/// TIC-80 syncs each section only once a frame, later requests for it are
+/// ignored. Loads that would hit a section already synced this frame, such
+/// as a dirty section that is written back first, wait for the next frame.
+/// Call [`BankManager::begin_frame`] at the start of every frame to run
+/// them.
+#[derive(Clone, Debug)]
+pub struct BankManager {
+    /// Bank currently in RAM for each section, by bit position.
+    banks: [u8; 8],
+    /// Bank waiting to be loaded next frame for each section.
+    pending: [Option<u8>; 8],
+    /// Sections waiting to be written back next frame.
+    storing: SyncMask,
+    dirty: SyncMask,
+    /// Sections synced this frame, in either direction.
+    synced: SyncMask,
+    sync: fn(SyncMask, u8, bool),
+}
+
+impl Default for BankManager {
+    fn default() -> Self {
+        Self {
+            banks: [0; 8],
+            pending: [None; 8],
+            storing: SyncMask::NONE,
+            dirty: SyncMask::NONE,
+            synced: SyncMask::NONE,
+            sync: |mask, bank, to_cart| sync(Some(mask), Some(bank as i8), to_cart),
+        }
+    }
+}
+
+impl BankManager {
+    /// Everything starts out from bank 0, as the cart boots.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start a frame and run the stores and loads deferred from the last
+    /// one, stores first. Returns the sections loaded.
+    pub fn begin_frame(&mut self) -> SyncMask {
+        self.synced = SyncMask::NONE;
+        let storing = std::mem::take(&mut self.storing);
+        self.store_sections(storing & self.dirty);
+        let mut loaded = SyncMask::NONE;
+        for bank in 0..BANKS as u8 {
+            let mask = self.take_pending(SyncMask::ALL, |pending| pending == bank);
+            if !mask.is_empty() {
+                loaded |= mask - self.run(mask, bank);
+            }
+        }
+        loaded
+    }
+
+    /// Load `sections` from `bank` into RAM. Sections edited since they
+    /// were loaded are written back to their own bank first, so they are
+    /// loaded next frame. Returns the sections holding `bank` now, the
+    /// others are pending.
+    pub fn load(&mut self, sections: SyncMask, bank: u8) -> Result<SyncMask, Tic80Error> {
+        check_bank(bank)?;
+        self.take_pending(sections, |_| true);
+        let changed = sections
+            .sections()
+            .filter(|&section| self.bank(section) != bank)
+            .fold(SyncMask::NONE, |mask, section| mask | section);
+        Ok(sections - self.run(changed, bank))
+    }
+
+    /// Load `sections` from `bank` even when they're already there,
+    /// dropping any edits. Returns the sections loaded now like
+    /// [`BankManager::load`].
+    pub fn reload(&mut self, sections: SyncMask, bank: u8) -> Result<SyncMask, Tic80Error> {
+        check_bank(bank)?;
+        self.take_pending(sections, |_| true);
+        self.dirty = self.dirty - sections;
+        self.storing = self.storing - sections;
+        Ok(sections - self.run(sections, bank))
+    }
+
+    /// Write back the dirty sections of `sections`, then sync the rest
+    /// that can still be synced this frame. Returns the sections deferred.
+    fn run(&mut self, sections: SyncMask, bank: u8) -> SyncMask {
+        // Writing back uses up the section's sync for this frame.
+        self.store_sections(sections & self.dirty);
+        let now = sections - self.synced;
+        if !now.is_empty() {
+            (self.sync)(now, bank, false);
+            self.synced |= now;
+            for section in now.sections() {
+                self.banks[section_index(section)] = bank;
+            }
+        }
+        let later = sections - now;
+        for section in later.sections() {
+            self.pending[section_index(section)] = Some(bank);
+        }
+        later
+    }
+
+    /// Clear the pending loads of `sections` whose bank matches `filter`,
+    /// returning their sections.
+    fn take_pending(&mut self, sections: SyncMask, filter: impl Fn(u8) -> bool) -> SyncMask {
+        let mut mask = SyncMask::NONE;
+        for (section, pending) in SyncMask::SECTIONS.into_iter().zip(&mut self.pending) {
+            if sections.contains(section) && pending.is_some_and(&filter) {
+                *pending = None;
+                mask |= section;
+            }
+        }
+        mask
+    }
+
+    /// The bank `section` was loaded from. For a mask of several sections,
+    /// the bank of the first one.
+    pub fn bank(&self, section: SyncMask) -> u8 {
+        section
+            .sections()
+            .next()
+            .map_or(0, |section| self.banks[section_index(section)])
+    }
+
+    /// Sections with a load waiting for the next frame.
+    pub fn pending(&self) -> SyncMask {
+        SyncMask::SECTIONS
+            .into_iter()
+            .zip(&self.pending)
+            .filter(|(_, pending)| pending.is_some())
+            .fold(SyncMask::NONE, |mask, (section, _)| mask | section)
+    }
+
+    /// Note that `sections` were changed in RAM and should be written back.
+    pub fn mark_dirty(&mut self, sections: SyncMask) {
+        self.dirty |= sections;
+    }
+
+    pub fn dirty(&self) -> SyncMask {
+        self.dirty
+    }
+
+    /// Write `sections` back to the banks they were loaded from. Returns
+    /// the sections written now, those already synced this frame stay
+    /// dirty and are written at the start of the next.
+    pub fn store(&mut self, sections: SyncMask) -> SyncMask {
+        let written = self.store_sections(sections);
+        self.storing |= sections - written;
+        written
+    }
+
+    /// Sections with a write-back waiting for the next frame.
+    pub fn storing(&self) -> SyncMask {
+        self.storing
+    }
+
+    /// Write back the sections not yet synced this frame, returning them.
+    fn store_sections(&mut self, sections: SyncMask) -> SyncMask {
+        let sections = sections - self.synced;
+        for bank in 0..BANKS as u8 {
+            let mask = sections
+                .sections()
+                .filter(|&section| self.banks[section_index(section)] == bank)
+                .fold(SyncMask::NONE, |mask, section| mask | section);
+            if !mask.is_empty() {
+                (self.sync)(mask, bank, true);
+            }
+        }
+        self.synced |= sections;
+        self.dirty = self.dirty - sections;
+        self.storing = self.storing - sections;
+        sections
+    }
+
+    /// Write back every section marked dirty, see [`BankManager::store`].
+    pub fn flush(&mut self) -> SyncMask {
+        self.store(self.dirty)
+    }
+}
+
+fn check_bank(bank: u8) -> Result<(), Tic80Error> {
+    if bank as usize >= BANKS {
+        return Err(Tic80Error::OutOfRange {
+            index: bank as usize,
+            len: BANKS,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    thread_local! {
+        static SYNCS: RefCell<Vec<(u8, u8, bool)>> = const { RefCell::new(Vec::new()) };
+    }
+
+    fn manager() -> BankManager {
+        SYNCS.with(|syncs| syncs.borrow_mut().clear());
+        BankManager {
+            sync: |mask, bank, to_cart| {
+                SYNCS.with(|syncs| syncs.borrow_mut().push((mask.bits(), bank, to_cart)))
+            },
+            ..BankManager::new()
+        }
+    }
+
+    fn syncs() -> Vec<(u8, u8, bool)> {
+        SYNCS.with(|syncs| syncs.borrow_mut().drain(..).collect())
+    }
+
+    const MAP: u8 = SyncMask::MAP.bits();
+    const TILES: u8 = SyncMask::TILES.bits();
+
+    #[test]
+    fn loads_only_changed_sections() {
+        let mut banks = manager();
+        let sections = SyncMask::MAP | SyncMask::TILES;
+        assert_eq!(banks.load(sections, 2).unwrap(), sections);
+        assert_eq!(syncs(), [(MAP | TILES, 2, false)]);
+        banks.begin_frame();
+        assert_eq!(banks.load(sections, 2).unwrap(), sections);
+        assert_eq!(syncs(), []);
+        assert_eq!(banks.bank(SyncMask::MAP), 2);
+    }
+
+    #[test]
+    fn dirty_sections_load_next_frame() {
+        let mut banks = manager();
+        banks.mark_dirty(SyncMask::MAP);
+        let loaded = banks.load(SyncMask::MAP | SyncMask::TILES, 3).unwrap();
+        assert_eq!(loaded, SyncMask::TILES);
+        assert_eq!(syncs(), [(MAP, 0, true), (TILES, 3, false)]);
+        assert_eq!(banks.bank(SyncMask::MAP), 0);
+        assert_eq!(banks.pending(), SyncMask::MAP);
+
+        assert_eq!(banks.begin_frame(), SyncMask::MAP);
+        assert_eq!(syncs(), [(MAP, 3, false)]);
+        assert_eq!(banks.bank(SyncMask::MAP), 3);
+        assert_eq!(banks.pending(), SyncMask::NONE);
+        assert_eq!(banks.dirty(), SyncMask::NONE);
+    }
+
+    #[test]
+    fn a_section_syncs_once_a_frame() {
+        let mut banks = manager();
+        banks.load(SyncMask::MAP, 1).unwrap();
+        assert_eq!(banks.reload(SyncMask::MAP, 2).unwrap(), SyncMask::NONE);
+        assert_eq!(banks.bank(SyncMask::MAP), 1);
+        banks.mark_dirty(SyncMask::MAP);
+        assert_eq!(banks.flush(), SyncMask::NONE);
+        assert_eq!(banks.dirty(), SyncMask::MAP);
+        assert_eq!(banks.storing(), SyncMask::MAP);
+        assert_eq!(syncs(), [(MAP, 1, false)]);
+
+        // The edit made after the reload was requested goes back first.
+        assert_eq!(banks.begin_frame(), SyncMask::NONE);
+        assert_eq!(syncs(), [(MAP, 1, true)]);
+        assert_eq!(banks.begin_frame(), SyncMask::MAP);
+        assert_eq!(syncs(), [(MAP, 2, false)]);
+    }
+
+    #[test]
+    fn deferred_stores_finish_next_frame() {
+        let mut banks = manager();
+        banks.load(SyncMask::MAP | SyncMask::TILES, 2).unwrap();
+        banks.mark_dirty(SyncMask::MAP | SyncMask::SFX);
+        assert_eq!(banks.flush(), SyncMask::SFX);
+        assert_eq!(banks.storing(), SyncMask::MAP);
+        syncs();
+        assert_eq!(banks.begin_frame(), SyncMask::NONE);
+        assert_eq!(syncs(), [(MAP, 2, true)]);
+        assert_eq!(banks.dirty(), SyncMask::NONE);
+        assert_eq!(banks.storing(), SyncMask::NONE);
+    }
+
+    #[test]
+    fn reloading_drops_a_deferred_store() {
+        let mut banks = manager();
+        banks.load(SyncMask::MAP, 2).unwrap();
+        banks.mark_dirty(SyncMask::MAP);
+        banks.store(SyncMask::MAP);
+        banks.begin_frame();
+        syncs();
+        // A fresh edit that can't be written this frame, then dropped.
+        banks.reload(SyncMask::TILES, 0).unwrap();
+        banks.mark_dirty(SyncMask::TILES);
+        banks.store(SyncMask::TILES);
+        banks.reload(SyncMask::TILES, 1).unwrap();
+        assert_eq!(banks.storing(), SyncMask::NONE);
+        syncs();
+        assert_eq!(banks.begin_frame(), SyncMask::TILES);
+        assert_eq!(syncs(), [(TILES, 1, false)]);
+    }
+
+    #[test]
+    fn a_later_load_replaces_a_pending_one() {
+        let mut banks = manager();
+        banks.mark_dirty(SyncMask::MAP);
+        banks.load(SyncMask::MAP, 4).unwrap();
+        assert_eq!(banks.load(SyncMask::MAP, 0).unwrap(), SyncMask::MAP);
+        assert_eq!(banks.pending(), SyncMask::NONE);
+        syncs();
+        assert_eq!(banks.begin_frame(), SyncMask::NONE);
+        assert_eq!(syncs(), []);
+    }
+
+    #[test]
+    fn rejects_missing_banks() {
+        let mut banks = manager();
+        assert!(matches!(
+            banks.load(SyncMask::MAP, 8),
+            Err(Tic80Error::OutOfRange { index: 8, len: 8 })
+        ));
+        assert!(banks.reload(SyncMask::MAP, 8).is_err());
+    }
+}
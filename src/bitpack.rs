@@ -0,0 +1,481 @@
+use heapless::Vec as Vector;
+
+pub use bitpack_derive::BitPack;
+
+use crate::tic80::*;
+use crate::tic80_error::Tic80Error;
+
+/// Bits available in persistent memory.
+pub const PMEM_BITS: usize = 1024 * 8;
+const PMEM_SLOTS: usize = 256;
+
+/// Bits needed to store values `0..=max`.
+pub const fn bits_for(max: usize) -> usize {
+    (usize::BITS - max.leading_zeros()) as usize
+}
+
+/// The larger of two sizes, for enum layouts.
+pub const fn max(a: usize, b: usize) -> usize {
+    if a > b {
+        a
+    } else {
+        b
+    }
+}
+
+/// A message built at compile time, so the derive's size check can report
+/// the computed size. Text past the capacity is cut off.
+pub struct ConstMessage {
+    bytes: [u8; 256],
+    len: usize,
+}
+
+impl ConstMessage {
+    pub const fn new() -> Self {
+        Self {
+            bytes: [0; 256],
+            len: 0,
+        }
+    }
+
+    pub const fn push_str(mut self, text: &str) -> Self {
+        let text = text.as_bytes();
+        let mut i = 0;
+        while i < text.len() && self.len < self.bytes.len() {
+            self.bytes[self.len] = text[i];
+            self.len += 1;
+            i += 1;
+        }
+        self
+    }
+
+    pub const fn push_number(mut self, mut value: usize) -> Self {
+        let mut digits = [0u8; 20];
+        let mut count = 0;
+        loop {
+            digits[count] = b'0' + (value % 10) as u8;
+            count += 1;
+            value /= 10;
+            if value == 0 {
+                break;
+            }
+        }
+        while count > 0 && self.len < self.bytes.len() {
+            count -= 1;
+            self.bytes[self.len] = digits[count];
+            self.len += 1;
+        }
+        self
+    }
+
+    pub const fn as_str(&self) -> &str {
+        let (bytes, _) = self.bytes.split_at(self.len);
+        match std::str::from_utf8(bytes) {
+            Ok(text) => text,
+            // Only possible when a multi-byte character was cut off.
+            Err(_) => "message is not valid UTF-8",
+        }
+    }
+}
+
+impl Default for ConstMessage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Writes values into a byte buffer, least significant bit first.
+pub struct BitWriter<'a> {
+    buffer: &'a mut [u8],
+    position: usize,
+}
+
+impl<'a> BitWriter<'a> {
+    pub fn new(buffer: &'a mut [u8]) -> Self {
+        Self {
+            buffer,
+            position: 0,
+        }
+    }
+
+    /// Write the low `bits` (up to 32) of `value`. Bits past the end of the
+    /// buffer are dropped.
+    pub fn write(&mut self, value: u32, bits: usize) {
+        for bit in 0..bits.min(32) {
+            let byte = self.position / 8;
+            if byte < self.buffer.len() {
+                let mask = 1 << (self.position % 8);
+                if value & (1 << bit) != 0 {
+                    self.buffer[byte] |= mask;
+                } else {
+                    self.buffer[byte] &= !mask;
+                }
+            }
+            self.position += 1;
+        }
+    }
+
+    /// Write `bits` zero bits of padding.
+    pub fn skip(&mut self, mut bits: usize) {
+        while bits > 0 {
+            let n = bits.min(32);
+            self.write(0, n);
+            bits -= n;
+        }
+    }
+
+    /// Bits written so far.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+}
+
+/// Reads values written by [`BitWriter`].
+pub struct BitReader<'a> {
+    buffer: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(buffer: &'a [u8]) -> Self {
+        Self {
+            buffer,
+            position: 0,
+        }
+    }
+
+    /// Read `bits` (up to 32) bits, past the end of the buffer reads zeros.
+    pub fn read(&mut self, bits: usize) -> u32 {
+        let mut value = 0;
+        for bit in 0..bits.min(32) {
+            let byte = self.position / 8;
+            if byte < self.buffer.len() && self.buffer[byte] & (1 << (self.position % 8)) != 0 {
+                value |= 1 << bit;
+            }
+            self.position += 1;
+        }
+        value
+    }
+
+    /// Step over `bits` bits.
+    pub fn skip(&mut self, bits: usize) {
+        self.position += bits;
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+}
+
+/// A type with a fixed size bit layout. Derive it with `#[derive(BitPack)]`,
+/// using `#[bits(n)]` on integer fields to store only `n` bits.
+pub trait BitPack: Sized {
+    /// Size of the layout in bits.
+    const BITS: usize;
+    fn pack(&self, writer: &mut BitWriter);
+    fn unpack(reader: &mut BitReader) -> Self;
+}
+
+/// Integers that can be stored in fewer bits than their size.
+pub trait BitPackBits: Sized {
+    fn pack_bits(&self, writer: &mut BitWriter, bits: usize);
+    fn unpack_bits(reader: &mut BitReader, bits: usize) -> Self;
+}
+
+macro_rules! impl_unsigned {
+    ($($t:ty),*) => {$(
+        impl BitPack for $t {
+            const BITS: usize = <$t>::BITS as usize;
+            fn pack(&self, writer: &mut BitWriter) {
+                writer.write(*self as u32, <Self as BitPack>::BITS);
+            }
+            fn unpack(reader: &mut BitReader) -> Self {
+                reader.read(<Self as BitPack>::BITS) as $t
+            }
+        }
+
+        impl BitPackBits for $t {
+            fn pack_bits(&self, writer: &mut BitWriter, bits: usize) {
+                writer.write(*self as u32, bits);
+            }
+            fn unpack_bits(reader: &mut BitReader, bits: usize) -> Self {
+                reader.read(bits) as $t
+            }
+        }
+    )*};
+}
+
+macro_rules! impl_signed {
+    ($($t:ty),*) => {$(
+        impl BitPack for $t {
+            const BITS: usize = <$t>::BITS as usize;
+            fn pack(&self, writer: &mut BitWriter) {
+                writer.write(*self as u32, <Self as BitPack>::BITS);
+            }
+            fn unpack(reader: &mut BitReader) -> Self {
+                reader.read(<Self as BitPack>::BITS) as $t
+            }
+        }
+
+        impl BitPackBits for $t {
+            fn pack_bits(&self, writer: &mut BitWriter, bits: usize) {
+                writer.write(*self as u32, bits);
+            }
+            fn unpack_bits(reader: &mut BitReader, bits: usize) -> Self {
+                // Sign extend from the top stored bit.
+                let shift = 32 - bits.clamp(1, 32) as u32;
+                (((reader.read(bits) << shift) as i32) >> shift) as $t
+            }
+        }
+    )*};
+}
+
+impl_unsigned!(u8, u16, u32);
+impl_signed!(i8, i16, i32);
+
+impl BitPack for bool {
+    const BITS: usize = 1;
+    fn pack(&self, writer: &mut BitWriter) {
+        writer.write(*self as u32, 1);
+    }
+    fn unpack(reader: &mut BitReader) -> Self {
+        reader.read(1) != 0
+    }
+}
+
+/// A flag bit, followed by the value's full layout whether present or not.
+impl<T: BitPack> BitPack for Option<T> {
+    const BITS: usize = 1 + T::BITS;
+    fn pack(&self, writer: &mut BitWriter) {
+        match self {
+            Some(value) => {
+                writer.write(1, 1);
+                value.pack(writer);
+            }
+            None => {
+                writer.write(0, 1);
+                writer.skip(T::BITS);
+            }
+        }
+    }
+    fn unpack(reader: &mut BitReader) -> Self {
+        if reader.read(1) != 0 {
+            Some(T::unpack(reader))
+        } else {
+            reader.skip(T::BITS);
+            None
+        }
+    }
+}
+
+impl<T: BitPack, const N: usize> BitPack for [T; N] {
+    const BITS: usize = N * T::BITS;
+    fn pack(&self, writer: &mut BitWriter) {
+        for value in self {
+            value.pack(writer);
+        }
+    }
+    fn unpack(reader: &mut BitReader) -> Self {
+        std::array::from_fn(|_| T::unpack(reader))
+    }
+}
+
+/// The length, then room for `N` values.
+impl<T: BitPack, const N: usize> BitPack for Vector<T, N> {
+    const BITS: usize = bits_for(N) + N * T::BITS;
+    fn pack(&self, writer: &mut BitWriter) {
+        writer.write(self.len() as u32, bits_for(N));
+        for value in self {
+            value.pack(writer);
+        }
+        writer.skip((N - self.len()) * T::BITS);
+    }
+    fn unpack(reader: &mut BitReader) -> Self {
+        let len = (reader.read(bits_for(N)) as usize).min(N);
+        let mut values = Vector::new();
+        for _ in 0..len {
+            // Okay to unwrap, the length is capped at the capacity.
+            values.push(T::unpack(reader)).ok().unwrap();
+        }
+        reader.skip((N - len) * T::BITS);
+        values
+    }
+}
+
+/// Pack `value` into 32 bit words, the layout `pmem` stores.
+pub fn pack_words<T: BitPack>(value: &T, words: &mut [u32]) -> Result<(), Tic80Error> {
+    let needed = T::BITS.div_ceil(32);
+    if needed > words.len() {
+        return Err(Tic80Error::OutOfRange {
+            index: needed,
+            len: words.len(),
+        });
+    }
+    let mut buffer = vec![0u8; needed * 4];
+    value.pack(&mut BitWriter::new(&mut buffer));
+    for (word, chunk) in words.iter_mut().zip(buffer.chunks(4)) {
+        *word = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+    }
+    Ok(())
+}
+
+/// Read a value written by [`pack_words`].
+pub fn unpack_words<T: BitPack>(words: &[u32]) -> Result<T, Tic80Error> {
+    let needed = T::BITS.div_ceil(32);
+    if needed > words.len() {
+        return Err(Tic80Error::OutOfRange {
+            index: needed,
+            len: words.len(),
+        });
+    }
+    let buffer: Vec<u8> = words[..needed]
+        .iter()
+        .flat_map(|w| w.to_le_bytes())
+        .collect();
+    Ok(T::unpack(&mut BitReader::new(&buffer)))
+}
+
+/// Pack `value` and write it across consecutive `pmem` slots from `first_slot`.
+pub fn save<T: BitPack>(value: &T, first_slot: usize) -> Result<(), Tic80Error> {
+    let slots = T::BITS.div_ceil(32);
+    if first_slot + slots > PMEM_SLOTS {
+        return Err(Tic80Error::OutOfRange {
+            index: first_slot + slots,
+            len: PMEM_SLOTS,
+        });
+    }
+    let mut words = vec![0; slots];
+    pack_words(value, &mut words)?;
+    for (i, word) in words.into_iter().enumerate() {
+        pmem_set((first_slot + i) as i32, word);
+    }
+    Ok(())
+}
+
+/// Read a value written by [`save`].
+pub fn load<T: BitPack>(first_slot: usize) -> Result<T, Tic80Error> {
+    let slots = T::BITS.div_ceil(32);
+    if first_slot + slots > PMEM_SLOTS {
+        return Err(Tic80Error::OutOfRange {
+            index: first_slot + slots,
+            len: PMEM_SLOTS,
+        });
+    }
+    let words: Vec<u32> = (first_slot..first_slot + slots)
+        .map(|slot| pmem_get(slot as i32))
+        .collect();
+    unpack_words(&words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+
+    #[derive(BitPack, Clone, PartialEq, Debug)]
+    struct Player {
+        #[bits(7)]
+        level: u8,
+        #[bits(5)]
+        offset: i8,
+        alive: bool,
+        gold: u32,
+        items: Vector<u16, 4>,
+        pet: Option<Pet>,
+    }
+
+    #[derive(BitPack, Clone, Copy, PartialEq, Debug)]
+    enum Pet {
+        Cat,
+        Dog(#[bits(4)] u8),
+        Bird {
+            #[bits(3)]
+            color: u8,
+            talks: bool,
+        },
+    }
+
+    fn round_trip<T: BitPack>(value: &T) -> T {
+        let mut words = vec![0; T::BITS.div_ceil(32)];
+        pack_words(value, &mut words).unwrap();
+        unpack_words(&words).unwrap()
+    }
+
+    #[test]
+    fn derived_sizes() {
+        assert_eq!(Pet::BITS, 2 + 4);
+        assert_eq!(Player::BITS, 7 + 5 + 1 + 32 + (3 + 4 * 16) + 1 + Pet::BITS);
+    }
+
+    #[test]
+    fn writer_and_reader_agree() {
+        let mut buffer = [0u8; 4];
+        let mut writer = BitWriter::new(&mut buffer);
+        writer.write(0b101, 3);
+        writer.skip(2);
+        writer.write(0x3ff, 10);
+        assert_eq!(writer.position(), 15);
+        assert_eq!(buffer, [0b1110_0101, 0b0111_1111, 0, 0]);
+        let mut reader = BitReader::new(&buffer);
+        assert_eq!(reader.read(3), 0b101);
+        reader.skip(2);
+        assert_eq!(reader.read(10), 0x3ff);
+        assert_eq!(reader.read(32), 0);
+    }
+
+    #[test]
+    fn signed_fields_sign_extend() {
+        let mut buffer = [0u8; 1];
+        (-3i8).pack_bits(&mut BitWriter::new(&mut buffer), 5);
+        assert_eq!(i8::unpack_bits(&mut BitReader::new(&buffer), 5), -3);
+    }
+
+    #[test]
+    fn random_values_round_trip() {
+        let mut rng = Rng::new(7);
+        for _ in 0..200 {
+            let mut items = Vector::new();
+            for _ in 0..rng.range(0, 5) {
+                items.push(rng.next_u32() as u16).unwrap();
+            }
+            let pet = match rng.range(0, 4) {
+                0 => None,
+                1 => Some(Pet::Cat),
+                2 => Some(Pet::Dog(rng.range(0, 16) as u8)),
+                _ => Some(Pet::Bird {
+                    color: rng.range(0, 8) as u8,
+                    talks: rng.range(0, 2) == 1,
+                }),
+            };
+            let player = Player {
+                level: rng.range(0, 128) as u8,
+                offset: rng.range(-16, 16) as i8,
+                alive: rng.range(0, 2) == 1,
+                gold: rng.next_u32(),
+                items,
+                pet,
+            };
+            assert_eq!(round_trip(&player), player);
+        }
+    }
+
+    #[test]
+    fn too_few_words_is_an_error() {
+        let mut words = [0; 1];
+        assert!(matches!(
+            pack_words(&[0u32; 2], &mut words),
+            Err(Tic80Error::OutOfRange { index: 2, len: 1 })
+        ));
+        assert!(unpack_words::<[u32; 2]>(&words).is_err());
+    }
+
+    #[test]
+    fn const_message_formats_numbers() {
+        let message = ConstMessage::new()
+            .push_str("needs ")
+            .push_number(9600)
+            .push_str(" of ")
+            .push_number(0);
+        assert_eq!(message.as_str(), "needs 9600 of 0");
+    }
+}
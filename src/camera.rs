@@ -0,0 +1,418 @@
+use crate::map_layer::{visible_area, LayeredMap, MapLayer, TILE_SIZE};
+use crate::rng::Rng;
+use crate::tic80::*;
+
+const SCREEN_W: f32 = WIDTH as f32;
+const SCREEN_H: f32 = HEIGHT as f32;
+
+/// A world rectangle in pixels.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Bounds {
+    pub x: f32,
+    pub y: f32,
+    pub w: f32,
+    pub h: f32,
+}
+
+impl Bounds {
+    pub fn new(x: f32, y: f32, w: f32, h: f32) -> Self {
+        Self { x, y, w, h }
+    }
+
+    /// A rectangle of map tiles.
+    pub fn tiles(x: i32, y: i32, w: i32, h: i32) -> Self {
+        let t = TILE_SIZE as f32;
+        Self::new(x as f32 * t, y as f32 * t, w as f32 * t, h as f32 * t)
+    }
+}
+
+/// The view onto the world. `x`, `y` is the world position of the screen's
+/// top-left corner before shaking.
+///
+/// Call [`Camera::follow`] and [`Camera::update`] once a frame, then draw
+/// with the camera's helpers, which take world coordinates.
+#[derive(Clone, Debug)]
+pub struct Camera {
+    pub x: f32,
+    pub y: f32,
+    target: Option<(f32, f32)>,
+    /// Size of the centred box the target moves in without moving the camera.
+    dead_zone: (f32, f32),
+    /// Fraction of the way to the wanted position moved each frame, 1.0
+    /// snaps.
+    smoothing: f32,
+    bounds: Option<Bounds>,
+    shake_strength: f32,
+    shake_frames: u32,
+    shake_left: u32,
+    shake_offset: (f32, f32),
+    rng: Rng,
+}
+
+impl Camera {
+    pub fn new() -> Self {
+        Self {
+            x: 0.0,
+            y: 0.0,
+            target: None,
+            dead_zone: (0.0, 0.0),
+            smoothing: 1.0,
+            bounds: None,
+            shake_strength: 0.0,
+            shake_frames: 0,
+            shake_left: 0,
+            shake_offset: (0.0, 0.0),
+            rng: Rng::new(0x0ca3_e7a5),
+        }
+    }
+
+    pub fn dead_zone(mut self, w: f32, h: f32) -> Self {
+        self.dead_zone = (w.max(0.0), h.max(0.0));
+        self
+    }
+
+    /// Smoothing in `0.0..=1.0`, lower follows more lazily.
+    pub fn smoothing(mut self, smoothing: f32) -> Self {
+        self.smoothing = smoothing.clamp(0.01, 1.0);
+        self
+    }
+
+    /// Keep the view inside `bounds`, usually the map. A dimension smaller
+    /// than the screen is centred.
+    pub fn with_bounds(mut self, bounds: Bounds) -> Self {
+        self.bounds = Some(bounds);
+        self
+    }
+
+    pub fn set_bounds(&mut self, bounds: Option<Bounds>) {
+        self.bounds = bounds;
+    }
+
+    pub fn bounds(&self) -> Option<Bounds> {
+        self.bounds
+    }
+
+    /// Track the world point `(x, y)`, e.g. the player's centre.
+    pub fn follow(&mut self, x: f32, y: f32) {
+        self.target = Some((x, y));
+    }
+
+    pub fn unfollow(&mut self) {
+        self.target = None;
+    }
+
+    /// Centre on the target at once, e.g. after loading a game.
+    pub fn snap(&mut self) {
+        if let Some((x, y)) = self.target {
+            self.x = x - SCREEN_W / 2.0;
+            self.y = y - SCREEN_H / 2.0;
+        }
+        self.clamp();
+    }
+
+    /// Restart the shake's random numbers from `seed`, e.g. for a replay.
+    pub fn reseed(&mut self, seed: u32) {
+        self.rng = Rng::new(seed);
+    }
+
+    /// Shake the view by up to `strength` pixels, fading out over `frames`.
+    /// A weaker shake doesn't cut a stronger one short.
+    pub fn shake(&mut self, strength: f32, frames: u32) {
+        let current = self.current_shake();
+        if strength >= current || self.shake_left == 0 {
+            self.shake_strength = strength;
+            self.shake_frames = frames.max(1);
+            self.shake_left = frames;
+        }
+    }
+
+    fn current_shake(&self) -> f32 {
+        if self.shake_left == 0 {
+            return 0.0;
+        }
+        self.shake_strength * self.shake_left as f32 / self.shake_frames as f32
+    }
+
+    /// Move towards the target and advance the shake.
+    pub fn update(&mut self) {
+        if let Some((tx, ty)) = self.target {
+            let (want_x, want_y) = self.wanted(tx, ty);
+            self.x += (want_x - self.x) * self.smoothing;
+            self.y += (want_y - self.y) * self.smoothing;
+            // Don't creep forever below a pixel.
+            if (want_x - self.x).abs() < 0.1 {
+                self.x = want_x;
+            }
+            if (want_y - self.y).abs() < 0.1 {
+                self.y = want_y;
+            }
+        }
+        self.clamp();
+
+        let strength = self.current_shake();
+        self.shake_offset = if strength > 0.0 {
+            (
+                self.rng.range_f32(-strength, strength),
+                self.rng.range_f32(-strength, strength),
+            )
+        } else {
+            (0.0, 0.0)
+        };
+        self.shake_left = self.shake_left.saturating_sub(1);
+    }
+
+    /// The position that puts the target back on the edge of the dead zone.
+    fn wanted(&self, tx: f32, ty: f32) -> (f32, f32) {
+        let axis = |pos: f32, target: f32, screen: f32, zone: f32| {
+            let low = pos + (screen - zone) / 2.0;
+            let high = low + zone;
+            if target < low {
+                pos - (low - target)
+            } else if target > high {
+                pos + (target - high)
+            } else {
+                pos
+            }
+        };
+        (
+            axis(self.x, tx, SCREEN_W, self.dead_zone.0),
+            axis(self.y, ty, SCREEN_H, self.dead_zone.1),
+        )
+    }
+
+    fn clamp(&mut self) {
+        let Some(b) = self.bounds else {
+            return;
+        };
+        let axis = |pos: f32, start: f32, size: f32, screen: f32| {
+            if size <= screen {
+                start - (screen - size) / 2.0
+            } else {
+                pos.clamp(start, start + size - screen)
+            }
+        };
+        self.x = axis(self.x, b.x, b.w, SCREEN_W);
+        self.y = axis(self.y, b.y, b.h, SCREEN_H);
+    }
+
+    /// The world pixel at the screen's top-left corner, shake included.
+    pub fn view(&self) -> (i32, i32) {
+        (
+            (self.x + self.shake_offset.0).round() as i32,
+            (self.y + self.shake_offset.1).round() as i32,
+        )
+    }
+
+    pub fn to_screen(&self, x: i32, y: i32) -> (i32, i32) {
+        let (vx, vy) = self.view();
+        (x - vx, y - vy)
+    }
+
+    pub fn to_world(&self, x: i32, y: i32) -> (i32, i32) {
+        let (vx, vy) = self.view();
+        (x + vx, y + vy)
+    }
+
+    /// Whether a world rectangle is at least partly on screen.
+    pub fn is_visible(&self, x: i32, y: i32, w: i32, h: i32) -> bool {
+        let (sx, sy) = self.to_screen(x, y);
+        sx + w > 0 && sy + h > 0 && sx < WIDTH as i32 && sy < HEIGHT as i32
+    }
+
+    // Drawing in world coordinates.
+
+    /// Draw sprite `id` with the settings of `spr`.
+    pub fn spr(&self, spr: &Spr, id: i32, x: i32, y: i32) {
+        let (sx, sy) = self.to_screen(x, y);
+        spr.spr(id, sx, sy);
+    }
+
+    /// Draw the `w` x `h` tiles of MAP at `(map_x, map_y)` with their
+    /// top-left corner at world pixel `(x, y)`. Only the tiles on screen are
+    /// drawn.
+    pub fn map(&self, map_x: i32, map_y: i32, w: i32, h: i32, x: i32, y: i32) {
+        let (vx, vy) = self.view();
+        let Some(area) = visible_area(w, h, vx - x, vy - y) else {
+            return;
+        };
+        Map::default()
+            .x(map_x + area.x)
+            .y(map_y + area.y)
+            .w(area.w)
+            .h(area.h)
+            .sx(area.sx)
+            .sy(area.sy)
+            .map();
+    }
+
+    pub fn layer(&self, layer: &MapLayer) {
+        let (vx, vy) = self.view();
+        layer.draw(vx, vy);
+    }
+
+    /// Draw the layers of `map` below the sprites.
+    pub fn layers_below<const N: usize>(&self, map: &LayeredMap<N>) {
+        let (vx, vy) = self.view();
+        map.draw_below(vx, vy);
+    }
+
+    /// Draw the layers of `map` above the sprites.
+    pub fn layers_above<const N: usize>(&self, map: &LayeredMap<N>) {
+        let (vx, vy) = self.view();
+        map.draw_above(vx, vy);
+    }
+
+    pub fn rect(&self, x: i32, y: i32, w: i32, h: i32, color: i32) {
+        let (sx, sy) = self.to_screen(x, y);
+        rect(sx, sy, w, h, color);
+    }
+
+    pub fn rectb(&self, x: i32, y: i32, w: i32, h: i32, color: i32) {
+        let (sx, sy) = self.to_screen(x, y);
+        rectb(sx, sy, w, h, color);
+    }
+
+    pub fn circ(&self, x: i32, y: i32, radius: i32, color: i8) {
+        let (sx, sy) = self.to_screen(x, y);
+        circ(sx, sy, radius, color);
+    }
+
+    pub fn circb(&self, x: i32, y: i32, radius: i32, color: i8) {
+        let (sx, sy) = self.to_screen(x, y);
+        circb(sx, sy, radius, color);
+    }
+
+    pub fn line(&self, x0: f32, y0: f32, x1: f32, y1: f32, color: i8) {
+        let (vx, vy) = self.view();
+        let (vx, vy) = (vx as f32, vy as f32);
+        line(x0 - vx, y0 - vy, x1 - vx, y1 - vy, color);
+    }
+
+    pub fn pix(&self, x: i32, y: i32, color: i8) {
+        let (sx, sy) = self.to_screen(x, y);
+        pix_set(sx, sy, color);
+    }
+
+    /// Print `text` with the settings of `print`, at world `(x, y)`.
+    /// Returns the width of the text like [`Print::print`]. `print` itself
+    /// is left as it was.
+    pub fn print<S: AsRef<str>>(&self, print: &Print, text: S, x: i32, y: i32) -> i32 {
+        let (sx, sy) = self.to_screen(x, y);
+        print.clone().x(sx).y(sy).print(text)
+    }
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snaps_to_centre_the_target() {
+        let mut camera = Camera::new();
+        camera.follow(500.0, 300.0);
+        camera.snap();
+        assert_eq!(
+            (camera.x, camera.y),
+            (500.0 - SCREEN_W / 2.0, 300.0 - SCREEN_H / 2.0)
+        );
+    }
+
+    #[test]
+    fn the_dead_zone_holds_still() {
+        let mut camera = Camera::new().dead_zone(40.0, 20.0);
+        camera.follow(SCREEN_W / 2.0, SCREEN_H / 2.0);
+        camera.snap();
+        camera.follow(SCREEN_W / 2.0 + 20.0, SCREEN_H / 2.0 - 10.0);
+        camera.update();
+        assert_eq!((camera.x, camera.y), (0.0, 0.0));
+        // Past the edge the camera moves just enough.
+        camera.follow(SCREEN_W / 2.0 + 25.0, SCREEN_H / 2.0 + 13.0);
+        camera.update();
+        assert_eq!((camera.x, camera.y), (5.0, 3.0));
+    }
+
+    #[test]
+    fn smoothing_eases_in() {
+        let mut camera = Camera::new().smoothing(0.5);
+        camera.follow(SCREEN_W / 2.0 + 100.0, SCREEN_H / 2.0);
+        camera.update();
+        assert_eq!(camera.x, 50.0);
+        for _ in 0..20 {
+            camera.update();
+        }
+        assert_eq!(camera.x, 100.0);
+    }
+
+    #[test]
+    fn stays_inside_the_bounds() {
+        let mut camera = Camera::new().with_bounds(Bounds::tiles(0, 0, 60, 34));
+        camera.follow(-50.0, 1000.0);
+        camera.snap();
+        assert_eq!((camera.x, camera.y), (0.0, 34.0 * 8.0 - SCREEN_H));
+    }
+
+    #[test]
+    fn centres_small_bounds() {
+        let mut camera = Camera::new().with_bounds(Bounds::new(100.0, 0.0, 120.0, 500.0));
+        camera.follow(0.0, 0.0);
+        camera.snap();
+        assert_eq!(camera.x, 100.0 - (SCREEN_W - 120.0) / 2.0);
+        assert_eq!(camera.y, 0.0);
+    }
+
+    #[test]
+    fn converts_between_world_and_screen() {
+        let mut camera = Camera::new();
+        camera.x = 40.4;
+        camera.y = -9.6;
+        assert_eq!(camera.view(), (40, -10));
+        assert_eq!(camera.to_screen(50, 0), (10, 10));
+        assert_eq!(camera.to_world(10, 10), (50, 0));
+        assert!(camera.is_visible(30, 0, 11, 1));
+        assert!(!camera.is_visible(30, 0, 10, 1));
+        assert!(!camera.is_visible(40 + WIDTH as i32, 0, 8, 8));
+    }
+
+    #[test]
+    fn shakes_fade_out() {
+        let mut camera = Camera::new();
+        camera.shake(4.0, 10);
+        for _ in 0..10 {
+            camera.update();
+            let (x, y) = camera.view();
+            assert!(x.abs() <= 4 && y.abs() <= 4);
+        }
+        camera.update();
+        assert_eq!(camera.view(), (0, 0));
+    }
+
+    #[test]
+    fn weaker_shakes_do_not_cut_stronger_ones() {
+        let mut camera = Camera::new();
+        camera.shake(8.0, 10);
+        camera.shake(1.0, 30);
+        assert_eq!((camera.shake_strength, camera.shake_left), (8.0, 10));
+        camera.shake(9.0, 5);
+        assert_eq!((camera.shake_strength, camera.shake_left), (9.0, 5));
+    }
+
+    #[test]
+    fn reseeding_repeats_the_shake() {
+        let mut a = Camera::new();
+        let mut b = Camera::new();
+        b.update();
+        b.reseed(3);
+        a.reseed(3);
+        for camera in [&mut a, &mut b] {
+            camera.shake(5.0, 8);
+            camera.update();
+        }
+        assert_eq!(a.view(), b.view());
+    }
+}
@@ -0,0 +1,176 @@
+use std::cell::RefCell;
+
+use crate::tic80::*;
+
+/// A screen rectangle.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ClipRect {
+    pub x: i32,
+    pub y: i32,
+    pub w: i32,
+    pub h: i32,
+}
+
+impl ClipRect {
+    pub const SCREEN: ClipRect = ClipRect {
+        x: 0,
+        y: 0,
+        w: WIDTH as i32,
+        h: HEIGHT as i32,
+    };
+
+    pub fn new(x: i32, y: i32, w: i32, h: i32) -> Self {
+        Self { x, y, w, h }
+    }
+
+    /// The overlap of both rectangles, empty ones have zero size.
+    pub fn intersect(&self, other: &ClipRect) -> ClipRect {
+        let x0 = self.x.max(other.x);
+        let y0 = self.y.max(other.y);
+        let x1 = (self.x + self.w).min(other.x + other.w);
+        let y1 = (self.y + self.h).min(other.y + other.h);
+        ClipRect::new(x0, y0, (x1 - x0).max(0), (y1 - y0).max(0))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.w <= 0 || self.h <= 0
+    }
+
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.x && y >= self.y && x < self.x + self.w && y < self.y + self.h
+    }
+
+    fn apply(&self) {
+        if *self == ClipRect::SCREEN {
+            Clip::clip_reset();
+        } else {
+            Clip::default()
+                .x(self.x)
+                .y(self.y)
+                .w(self.w)
+                .h(self.h)
+                .clip();
+        }
+    }
+}
+
+thread_local! {
+    static CLIP_STACK: RefCell<Vec<ClipRect>> = const { RefCell::new(Vec::new()) };
+}
+
+/// The clip region in effect, the whole screen when nothing is pushed.
+pub fn current_clip() -> ClipRect {
+    CLIP_STACK.with(|stack| stack.borrow().last().copied().unwrap_or(ClipRect::SCREEN))
+}
+
+/// Clip drawing to `rect` within the current clip region until the
+/// returned guard is dropped, which restores the previous region.
+///
+/// ```ignore
+/// let _panel = push_clip(ClipRect::new(20, 20, 100, 60));
+/// {
+///     let _list = push_clip(ClipRect::new(24, 30, 92, 40));
+///     // Clipped to both.
+/// }
+/// // Clipped to the panel again.
+/// ```
+pub fn push_clip(rect: ClipRect) -> ClipGuard {
+    let clip = current_clip().intersect(&rect);
+    CLIP_STACK.with(|stack| stack.borrow_mut().push(clip));
+    clip.apply();
+    ClipGuard { _private: () }
+}
+
+/// Restores the previous clip region when dropped, see [`push_clip`].
+#[must_use = "the clip region is restored as soon as the guard is dropped"]
+pub struct ClipGuard {
+    _private: (),
+}
+
+impl Drop for ClipGuard {
+    fn drop(&mut self) {
+        CLIP_STACK.with(|stack| stack.borrow_mut().pop());
+        current_clip().apply();
+    }
+}
+
+// Software drawing straight into the framebuffer with `poke4`, a 4 bit
+// address per pixel. These skip `clip`, so they check the clip stack
+// themselves.
+
+fn put_pixel(x: i32, y: i32, color: u8) {
+    // The framebuffer starts at address 0.
+    poke4(y * WIDTH as i32 + x, color & 0x0f);
+}
+
+/// Set one pixel if it's inside the clip region.
+pub fn pixel(x: i32, y: i32, color: u8) {
+    if current_clip().contains(x, y) {
+        put_pixel(x, y, color);
+    }
+}
+
+/// Fill a rectangle, clipped.
+pub fn fill_rect(x: i32, y: i32, w: i32, h: i32, color: u8) {
+    let area = current_clip().intersect(&ClipRect::new(x, y, w, h));
+    for y in area.y..area.y + area.h {
+        for x in area.x..area.x + area.w {
+            put_pixel(x, y, color);
+        }
+    }
+}
+
+pub fn hline(x: i32, y: i32, w: i32, color: u8) {
+    fill_rect(x, y, w, 1, color);
+}
+
+pub fn vline(x: i32, y: i32, h: i32, color: u8) {
+    fill_rect(x, y, 1, h, color);
+}
+
+/// A rectangle outline, clipped.
+pub fn rect_border(x: i32, y: i32, w: i32, h: i32, color: u8) {
+    hline(x, y, w, color);
+    hline(x, y + h - 1, w, color);
+    vline(x, y + 1, h - 2, color);
+    vline(x + w - 1, y + 1, h - 2, color);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intersects_overlapping_rects() {
+        let a = ClipRect::new(10, 10, 100, 50);
+        let b = ClipRect::new(60, -5, 100, 30);
+        assert_eq!(a.intersect(&b), ClipRect::new(60, 10, 50, 15));
+        assert_eq!(a.intersect(&b), b.intersect(&a));
+        assert_eq!(ClipRect::SCREEN.intersect(&a), a);
+    }
+
+    #[test]
+    fn disjoint_rects_intersect_empty() {
+        let a = ClipRect::new(0, 0, 10, 10);
+        let touching = a.intersect(&ClipRect::new(10, 0, 10, 10));
+        assert!(touching.is_empty());
+        let apart = a.intersect(&ClipRect::new(50, 50, 10, 10));
+        assert!(apart.is_empty());
+        assert!(apart.w >= 0 && apart.h >= 0);
+    }
+
+    #[test]
+    fn contains_is_half_open() {
+        let rect = ClipRect::new(5, 5, 3, 2);
+        assert!(rect.contains(5, 5));
+        assert!(rect.contains(7, 6));
+        assert!(!rect.contains(8, 6));
+        assert!(!rect.contains(7, 7));
+        assert!(!rect.contains(4, 5));
+    }
+
+    #[test]
+    fn the_screen_is_the_default_clip() {
+        assert_eq!(current_clip(), ClipRect::SCREEN);
+    }
+}
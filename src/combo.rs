@@ -0,0 +1,354 @@
+use heapless::Vec as Vector;
+
+use crate::tic80::*;
+use crate::tic80_error::Tic80Error;
+
+pub const MAX_STEPS: usize = 8;
+pub const MAX_SEQUENCES: usize = 16;
+const HISTORY: usize = 16;
+/// Frames allowed between steps unless a sequence says otherwise.
+pub const DEFAULT_WINDOW: u32 = 20;
+
+/// The eight buttons of one gamepad, in `btn` id order.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Button {
+    Up,
+    Down,
+    Left,
+    Right,
+    A,
+    B,
+    X,
+    Y,
+}
+
+impl Button {
+    pub const ALL: [Button; 8] = [
+        Button::Up,
+        Button::Down,
+        Button::Left,
+        Button::Right,
+        Button::A,
+        Button::B,
+        Button::X,
+        Button::Y,
+    ];
+
+    /// The `btn` id of this button for `player` 0..3.
+    pub fn id(self, player: u8) -> i32 {
+        player as i32 * 8 + self as i32
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Step {
+    pub button: Button,
+    /// Frames allowed since the previous step, ignored on the first step.
+    pub window: u32,
+}
+
+/// Buttons to press in order to produce `output`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Sequence<T> {
+    pub output: T,
+    pub steps: Vector<Step, MAX_STEPS>,
+}
+
+impl<T> Sequence<T> {
+    /// A sequence with [`DEFAULT_WINDOW`] between every step. Buttons past
+    /// [`MAX_STEPS`] are dropped.
+    pub fn new(output: T, buttons: &[Button]) -> Self {
+        Self {
+            output,
+            steps: buttons
+                .iter()
+                .take(MAX_STEPS)
+                .map(|&button| Step {
+                    button,
+                    window: DEFAULT_WINDOW,
+                })
+                .collect(),
+        }
+    }
+
+    /// Change the window before step `index`.
+    pub fn window(mut self, index: usize, frames: u32) -> Self {
+        if let Some(step) = self.steps.get_mut(index) {
+            step.window = frames;
+        }
+        self
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Press {
+    button: Button,
+    frame: u32,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Pending {
+    sequence: usize,
+    deadline: u32,
+}
+
+/// Recognises button sequences, like Down, Right, A for a fireball, from a
+/// timed history of presses.
+///
+/// When a completed sequence is also the start of a longer one, it waits for
+/// the longer one's next step before firing, so both can be registered.
+pub struct ComboRecognizer<T> {
+    player: u8,
+    sequences: Vector<Sequence<T>, MAX_SEQUENCES>,
+    history: Vector<Press, HISTORY>,
+    pending: Option<Pending>,
+}
+
+impl<T: Copy> ComboRecognizer<T> {
+    pub fn new(player: u8) -> Self {
+        Self {
+            player,
+            sequences: Vector::new(),
+            history: Vector::new(),
+            pending: None,
+        }
+    }
+
+    /// Register a sequence, sequences without steps are ignored. Fails
+    /// past [`MAX_SEQUENCES`].
+    pub fn with_sequence(mut self, sequence: Sequence<T>) -> Result<Self, Tic80Error> {
+        if !sequence.steps.is_empty() {
+            let len = self.sequences.len();
+            self.sequences
+                .push(sequence)
+                .map_err(|_| Tic80Error::OutOfRange {
+                    index: len,
+                    len: MAX_SEQUENCES,
+                })?;
+        }
+        Ok(self)
+    }
+
+    /// Read this frame's presses with `btnp`. `frame` is the game's frame
+    /// counter. Returns the output of a sequence completed this frame.
+    pub fn update(&mut self, frame: u32) -> Option<T> {
+        let mut fired = self.expire(frame);
+        for button in Button::ALL {
+            if Btnp::default().id(button.id(self.player)).btnp() {
+                fired = self.press(button, frame).or(fired);
+            }
+        }
+        fired
+    }
+
+    /// Fire a pending sequence once its longer alternatives time out.
+    fn expire(&mut self, frame: u32) -> Option<T> {
+        let pending = self.pending?;
+        if frame <= pending.deadline {
+            return None;
+        }
+        self.pending = None;
+        self.history.clear();
+        Some(self.sequences[pending.sequence].output)
+    }
+
+    /// Feed a press from another source, e.g. an action or a replay.
+    pub fn press(&mut self, button: Button, frame: u32) -> Option<T> {
+        if self.history.is_full() {
+            self.history.remove(0);
+        }
+        // Okay to unwrap, there's room after removing the oldest press.
+        self.history.push(Press { button, frame }).unwrap();
+
+        let complete = (0..self.sequences.len())
+            .filter(|&i| self.suffix_matches(i, self.sequences[i].steps.len()))
+            .max_by_key(|&i| self.sequences[i].steps.len());
+        // Only longer sequences can still outdo the completed one.
+        let longest_complete = complete.map_or(1, |i| self.sequences[i].steps.len());
+        let next_window = self.longer_partial(longest_complete);
+
+        match (complete, next_window) {
+            (Some(sequence), Some(window)) => {
+                self.pending = Some(Pending {
+                    sequence,
+                    deadline: frame.saturating_add(window),
+                });
+                None
+            }
+            (Some(sequence), None) => {
+                self.pending = None;
+                self.history.clear();
+                Some(self.sequences[sequence].output)
+            }
+            (None, Some(window)) => {
+                if let Some(pending) = &mut self.pending {
+                    pending.deadline = frame.saturating_add(window);
+                }
+                None
+            }
+            (None, None) => {
+                // This press ended whatever was pending, fire that and keep
+                // the press as a possible start of the next sequence.
+                let pending = self.pending.take()?;
+                self.history.clear();
+                // Okay to unwrap, the history was just cleared.
+                self.history.push(Press { button, frame }).unwrap();
+                Some(self.sequences[pending.sequence].output)
+            }
+        }
+    }
+
+    /// Whether the last `len` presses match the first `len` steps of
+    /// sequence `index` within their windows.
+    fn suffix_matches(&self, index: usize, len: usize) -> bool {
+        let steps = &self.sequences[index].steps;
+        if len == 0 || len > steps.len() || len > self.history.len() {
+            return false;
+        }
+        let presses = &self.history[self.history.len() - len..];
+        presses
+            .iter()
+            .zip(steps)
+            .enumerate()
+            .all(|(i, (press, step))| {
+                press.button == step.button
+                    && (i == 0 || press.frame.wrapping_sub(presses[i - 1].frame) <= step.window)
+            })
+    }
+
+    /// The window of the next step of a longer sequence that the last
+    /// `min_len` or more presses have started.
+    fn longer_partial(&self, min_len: usize) -> Option<u32> {
+        self.sequences
+            .iter()
+            .enumerate()
+            .flat_map(|(i, sequence)| {
+                (min_len..sequence.steps.len())
+                    .filter(move |&len| self.suffix_matches(i, len))
+                    .map(move |len| sequence.steps[len].window)
+            })
+            .max()
+    }
+
+    /// Forget the presses so far.
+    pub fn reset(&mut self) {
+        self.history.clear();
+        self.pending = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Button::*;
+
+    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+    enum Move {
+        Punch,
+        Fireball,
+        Super,
+    }
+
+    fn recognizer() -> ComboRecognizer<Move> {
+        ComboRecognizer::new(0)
+            .with_sequence(Sequence::new(Move::Fireball, &[Down, Right, A]))
+            .and_then(|r| r.with_sequence(Sequence::new(Move::Punch, &[A])))
+            .and_then(|r| r.with_sequence(Sequence::new(Move::Super, &[Down, Right, A, A])))
+            .unwrap()
+    }
+
+    /// Press `buttons` one frame apart from `frame`, collecting outputs.
+    fn press_all(r: &mut ComboRecognizer<Move>, frame: u32, buttons: &[Button]) -> Vec<Move> {
+        let mut fired = Vec::new();
+        for (i, &button) in buttons.iter().enumerate() {
+            let frame = frame + i as u32;
+            fired.extend(r.expire(frame));
+            fired.extend(r.press(button, frame));
+        }
+        fired
+    }
+
+    /// Advance frames without presses until `end`, collecting outputs.
+    fn wait(r: &mut ComboRecognizer<Move>, from: u32, end: u32) -> Vec<Move> {
+        (from..=end).filter_map(|frame| r.expire(frame)).collect()
+    }
+
+    #[test]
+    fn single_step_fires_at_once() {
+        let mut r = ComboRecognizer::new(0)
+            .with_sequence(Sequence::new(Move::Punch, &[A]))
+            .unwrap();
+        assert_eq!(r.press(A, 0), Some(Move::Punch));
+        assert_eq!(r.press(B, 1), None);
+    }
+
+    #[test]
+    fn a_prefix_waits_for_the_longer_sequence() {
+        let mut r = recognizer();
+        assert_eq!(press_all(&mut r, 10, &[Down, Right, A]), []);
+        assert_eq!(wait(&mut r, 13, 12 + DEFAULT_WINDOW), []);
+        assert_eq!(wait(&mut r, 13 + DEFAULT_WINDOW, 40), [Move::Fireball]);
+    }
+
+    #[test]
+    fn the_longest_sequence_wins() {
+        let mut r = recognizer();
+        assert_eq!(press_all(&mut r, 0, &[Down, Right, A, A]), [Move::Super]);
+        assert_eq!(wait(&mut r, 4, 100), []);
+    }
+
+    #[test]
+    fn another_press_fires_the_pending_sequence() {
+        let mut r = recognizer();
+        assert_eq!(press_all(&mut r, 0, &[Down, Right, A, B]), [Move::Fireball]);
+        // The B press starts over.
+        assert_eq!(press_all(&mut r, 4, &[Down, Right, A, A]), [Move::Super]);
+    }
+
+    #[test]
+    fn slow_steps_break_the_sequence() {
+        let mut r = recognizer();
+        r.press(Down, 0);
+        r.press(Right, DEFAULT_WINDOW + 1);
+        let mut fired: Vec<_> = r.press(A, DEFAULT_WINDOW + 2).into_iter().collect();
+        fired.extend(wait(&mut r, DEFAULT_WINDOW + 3, 100));
+        assert_eq!(fired, [Move::Punch]);
+    }
+
+    #[test]
+    fn frames_going_backwards_do_not_match() {
+        let mut r = recognizer();
+        r.press(Down, 50);
+        r.press(Right, 10);
+        let fired: Vec<_> = r
+            .press(A, 11)
+            .into_iter()
+            .chain(wait(&mut r, 12, 60))
+            .collect();
+        assert_eq!(fired, [Move::Punch]);
+    }
+
+    #[test]
+    fn too_many_sequences_is_an_error() {
+        let mut r = ComboRecognizer::new(0);
+        for _ in 0..MAX_SEQUENCES {
+            r = r.with_sequence(Sequence::new(Move::Punch, &[A])).unwrap();
+        }
+        assert!(matches!(
+            r.with_sequence(Sequence::new(Move::Punch, &[A])),
+            Err(Tic80Error::OutOfRange { index: 16, len: 16 })
+        ));
+    }
+
+    #[test]
+    fn long_input_keeps_working() {
+        let mut r = recognizer();
+        let noise = [B; HISTORY * 2];
+        press_all(&mut r, 0, &noise);
+        let frame = noise.len() as u32;
+        assert_eq!(
+            press_all(&mut r, frame, &[Down, Right, A, A]),
+            [Move::Super]
+        );
+    }
+}
@@ -1,16 +1,173 @@
 #[cfg(feature = "buddy-alloc")]
 mod alloc;
+pub mod actions;
+pub mod audio;
+pub mod autotile;
+pub mod banks;
+pub mod bitpack;
+pub mod camera;
+pub mod clip;
+pub mod combo;
+pub mod map_layer;
+pub mod mouse;
+pub mod music_data;
+pub mod music_player;
+#[macro_use]
+pub mod note;
+pub mod persistent;
+pub mod positional;
+pub mod replay;
+pub mod rng;
+pub mod rooms;
+pub mod rune;
+pub mod save_slots;
+pub mod sequencer;
+pub mod sfx_data;
+pub mod sfxr;
+pub mod synth;
+pub mod text_input;
 mod tic80;
 mod tic80_error;
+mod waveform;
 
 use std::cell::RefCell;
 
+use heapless::Vec as Vector;
+
+use actions::{Action, Actions, Bindings, RebindMenu};
+use banks::{BankManager, BANKS};
+use combo::{Button, ComboRecognizer, Sequence};
+use bitpack::BitPack;
+use camera::{Bounds, Camera};
+use map_layer::{LayerKind, LayeredMap, MapLayer};
+use persistent::{LoadStatus, Schema};
+use replay::{InputReplay, Recording};
+use rooms::{RoomTransitions, Rooms, TransitionStyle};
+use rune::{RuneRecognizer, StrokeCapture, Template};
+use save_slots::{migrate_to_settings_layout, SaveSlots, SlotMeta, SlotPicker};
+use text_input::{TextInput, TextInputEvent};
 use tic80::*;
 use tic80_error::Tic80Error;
 
+const SAVE_SCHEMA: Schema = Schema {
+    magic: u32::from_le_bytes(*b"WIZT"),
+    version: 2,
+    first_version: 1,
+    migrations: &[migrate_to_settings_layout],
+};
+/// Each floor keeps its art and map in its own bank.
+const FLOOR_SECTIONS: SyncMask = SyncMask::TILES
+    .union(SyncMask::SPRITES)
+    .union(SyncMask::MAP)
+    .union(SyncMask::FLAGS);
+const AUTOSAVE_FRAMES: u32 = 60 * 10;
+const START_X: i16 = 96;
+const START_Y: i16 = 24;
+/// Size of the player sprite in pixels.
+const PLAYER_SIZE: i32 = 16;
+/// Size of the map the floors use, in tiles.
+const MAP_W: i32 = 240;
+const MAP_H: i32 = 136;
+/// Map tile marking the top-left corner of a room.
+const ROOM_MARKER: i32 = 255;
+/// Drawn in place of the room markers.
+const ROOM_FLOOR: i32 = 0;
+const NAME_LEN: usize = 12;
+
+/// What a save slot stores besides its [`SlotMeta`].
+#[derive(BitPack, Clone)]
+struct SaveState {
+    player_x: i16,
+    player_y: i16,
+    /// The wizard's name, ASCII.
+    name: Vector<u8, NAME_LEN>,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Spell {
+    Fireball,
+    Frost,
+    Blink,
+}
+
+impl Spell {
+    fn name(self) -> &'static str {
+        match self {
+            Spell::Fireball => "FIREBALL",
+            Spell::Frost => "FROST",
+            Spell::Blink => "BLINK",
+        }
+    }
+}
+
+fn spellbook() -> ComboRecognizer<Spell> {
+    use Button::*;
+    // Okay to unwrap, three sequences fit.
+    ComboRecognizer::new(0)
+        .with_sequence(Sequence::new(Spell::Fireball, &[Down, Right, A]))
+        .and_then(|combos| combos.with_sequence(Sequence::new(Spell::Frost, &[Down, Left, A])))
+        .and_then(|combos| {
+            combos.with_sequence(Sequence::new(Spell::Blink, &[Up, Up, B]).window(1, 12))
+        })
+        .unwrap()
+}
+
+fn runebook() -> RuneRecognizer<Spell> {
+    let circle: Vec<(f32, f32)> = (0..=24)
+        .map(|i| {
+            let angle = i as f32 / 24.0 * std::f32::consts::TAU;
+            (angle.cos(), angle.sin())
+        })
+        .collect();
+    // Okay to unwrap, the shapes all have distinct points.
+    RuneRecognizer::new()
+        .with_template(
+            Template::new(
+                Spell::Fireball,
+                &[(0.0, -1.0), (1.0, 0.8), (-1.0, 0.8), (0.0, -1.0)],
+            )
+            .unwrap(),
+        )
+        .with_template(
+            Template::new(
+                Spell::Frost,
+                &[(-1.0, -0.5), (-0.5, 0.5), (0.0, -0.5), (0.5, 0.5), (1.0, -0.5)],
+            )
+            .unwrap(),
+        )
+        .with_template(Template::new(Spell::Blink, &circle).unwrap())
+}
+
+enum Screen {
+    Title(SlotPicker),
+    /// Naming the wizard before a new game in `slot`.
+    NameEntry { input: TextInput, slot: usize },
+    Playing { slot: usize },
+    Options { menu: RebindMenu, slot: usize },
+}
+
 struct Game {
     tic: i32,
+    screen: Screen,
+    actions: Actions,
+    replay: InputReplay,
+    spells: ComboRecognizer<Spell>,
+    runes: RuneRecognizer<Spell>,
+    stroke: StrokeCapture,
+    /// Print drawn runes as template source instead of casting them.
+    record_runes: bool,
+    /// The last spell cast and the frame it was cast on.
+    last_spell: Option<(Spell, i32)>,
+    recording: Option<Recording>,
+    saves: SaveSlots<SaveState>,
+    banks: BankManager,
+    meta: SlotMeta,
+    play_frames: u32,
+    name: String,
     player: Player,
+    map: LayeredMap<4>,
+    camera: Camera,
+    rooms: RoomTransitions,
 }
 
 struct Player {
@@ -18,15 +175,210 @@ struct Player {
     y: i32,
 }
 
-thread_local! {
-    static GAME: RefCell<Game> = RefCell::new(
-        Game {
+impl Game {
+    fn new() -> Self {
+        let (saves, status) = SaveSlots::open(SAVE_SCHEMA);
+        if matches!(status, LoadStatus::Corrupted | LoadStatus::Unsupported { .. }) {
+            trace(format!("Save data reset: {:?}\0", status), None);
+        }
+        let bindings: Option<Bindings> = saves.load_settings().unwrap_or_default();
+        Self {
             tic: 0,
-        player: Player {
-            x: 96,
-            y: 24,
-        },
-    });
+            screen: Screen::Title(SlotPicker::new()),
+            actions: Actions::new(bindings.unwrap_or_default()),
+            replay: InputReplay::new(),
+            spells: spellbook(),
+            runes: runebook(),
+            stroke: StrokeCapture::new(),
+            record_runes: false,
+            last_spell: None,
+            recording: None,
+            saves,
+            banks: BankManager::new(),
+            meta: SlotMeta::default(),
+            play_frames: 0,
+            name: String::new(),
+            player: Player {
+                x: START_X.into(),
+                y: START_Y.into(),
+            },
+            // Okay to unwrap, the map has room for four layers.
+            map: LayeredMap::new()
+                .with_layer(MapLayer::new(LayerKind::Background, 0, 0, MAP_W, MAP_H))
+                .unwrap(),
+            camera: Camera::new()
+                .dead_zone(48.0, 32.0)
+                .smoothing(0.2)
+                .with_bounds(Bounds::tiles(0, 0, MAP_W, MAP_H)),
+            rooms: RoomTransitions::new(Rooms::default()).style(TransitionStyle::Scroll),
+        }
+    }
+
+    /// Begin a new game in `slot`, replacing what was there.
+    fn new_game(&mut self, slot: usize, name: &str) -> Result<(), Tic80Error> {
+        let meta = SlotMeta {
+            floor: 1,
+            level: 1,
+            ..Default::default()
+        };
+        let state = SaveState {
+            player_x: START_X,
+            player_y: START_Y,
+            name: name.bytes().take(NAME_LEN).collect(),
+        };
+        self.saves.save(slot, meta, state)?;
+        self.start(slot)
+    }
+
+    /// Continue the game in `slot`.
+    fn start(&mut self, slot: usize) -> Result<(), Tic80Error> {
+        let file = match self.saves.load(slot)? {
+            Some(file) => file,
+            None => return self.new_game(slot, "WIZARD"),
+        };
+        self.meta = file.meta;
+        self.play_frames = file.meta.play_time * 60;
+        self.name = String::from_utf8_lossy(&file.data.name).into_owned();
+        self.player.x = file.data.player_x.into();
+        self.player.y = file.data.player_y.into();
+        self.screen = Screen::Playing { slot };
+        self.enter_floor(file.meta.floor)?;
+        self.save()
+    }
+
+    /// Swap in the art and map of `floor`, floors past the last bank reuse it.
+    fn enter_floor(&mut self, floor: u8) -> Result<(), Tic80Error> {
+        self.meta.floor = floor;
+        let bank = (floor.max(1) - 1).min(BANKS as u8 - 1);
+        let loaded = self.banks.load(FLOOR_SECTIONS, bank)?;
+        // Otherwise the map is switched at the start of the next frame.
+        if loaded.contains(SyncMask::MAP) {
+            self.map_loaded()?;
+        }
+        Ok(())
+    }
+
+    /// Find the rooms of the map now in RAM.
+    fn map_loaded(&mut self) -> Result<(), Tic80Error> {
+        let rooms = Rooms::from_markers(0, 0, MAP_W, MAP_H, ROOM_MARKER, ROOM_FLOOR)?;
+        self.rooms.set_rooms(rooms);
+        let (x, y) = self.follow_player();
+        self.rooms.enter(&mut self.camera, x, y);
+        Ok(())
+    }
+
+    fn cast(&mut self, spell: Spell) {
+        trace(format!("Cast {:?}\0", spell), None);
+        self.last_spell = Some((spell, self.tic));
+        self.camera.shake(2.0, 12);
+    }
+
+    /// A frame of input, spells and movement.
+    fn play(&mut self) -> Result<(), Tic80Error> {
+        self.play_frames += 1;
+        if self.play_frames.is_multiple_of(AUTOSAVE_FRAMES) {
+            self.save()?;
+        }
+
+        if let Some(spell) = self.spells.update(self.tic as u32) {
+            self.cast(spell);
+        }
+        if let Some(stroke) = self.stroke.update(self.actions.mouse()) {
+            if self.record_runes {
+                if let Some(template) = Template::from_stroke((), &stroke) {
+                    trace(format!("{}\0", template.to_source("Spell::")), None);
+                }
+            } else if let Some(rune) = self.runes.recognize(&stroke) {
+                trace(format!("Rune score {:.2}\0", rune.score), None);
+                self.cast(rune.output);
+            }
+        }
+
+        if self.actions.repeat(Action::MoveUp, 6, 30) {
+            self.player.y -= 16;
+        }
+
+        if self.actions.repeat(Action::MoveDown, 6, 30) {
+            self.player.y += 16;
+        }
+
+        if self.actions.repeat(Action::MoveLeft, 6, 30) {
+            self.player.x -= 16;
+        }
+
+        if self.actions.repeat(Action::MoveRight, 6, 30) {
+            self.player.x += 16;
+        }
+
+        Ok(())
+    }
+
+    /// Point the camera at the middle of the player, which is returned.
+    fn follow_player(&mut self) -> (i32, i32) {
+        let x = self.player.x + PLAYER_SIZE / 2;
+        let y = self.player.y + PLAYER_SIZE / 2;
+        self.camera.follow(x as f32, y as f32);
+        (x, y)
+    }
+
+    /// Ctrl+R starts and stops recording input, Ctrl+P replays the last
+    /// recording and Ctrl+T toggles recording runes. Checked before the
+    /// replay writes this frame's input.
+    fn debug_replay_keys(&mut self) {
+        if !key(KEY_CTRL) {
+            return;
+        }
+        if Keyp::default().id(KEY_R).keyp() {
+            if self.replay.is_recording() {
+                self.recording = self.replay.stop();
+                if let Some(recording) = &self.recording {
+                    trace(
+                        format!(
+                            "Recorded {} frames in {} bytes\0",
+                            recording.frames(),
+                            recording.to_bytes().len()
+                        ),
+                        None,
+                    );
+                }
+            } else {
+                let seed = tstamp();
+                self.replay.record(seed);
+                self.reseed(seed);
+                trace("Recording input\0", None);
+            }
+        } else if Keyp::default().id(KEY_T).keyp() {
+            self.record_runes = !self.record_runes;
+        } else if Keyp::default().id(KEY_P).keyp() {
+            if let Some(recording) = self.recording.clone() {
+                let seed = self.replay.replay(recording);
+                self.reseed(seed);
+                trace("Replaying input\0", None);
+            }
+        }
+    }
+
+    /// Restart the game's random numbers, which only the camera shake uses.
+    fn reseed(&mut self, seed: u32) {
+        self.camera.reseed(seed);
+    }
+
+    fn save(&mut self) -> Result<(), Tic80Error> {
+        if let Screen::Playing { slot } = self.screen {
+            self.meta.play_time = self.play_frames / 60;
+            let state = SaveState {
+                player_x: self.player.x as i16,
+                player_y: self.player.y as i16,
+                name: self.name.bytes().take(NAME_LEN).collect(),
+            };
+            self.saves.save(slot, self.meta, state)?;
+        }
+        Ok(())
+    }
+}
+
+thread_local! {
+    static GAME: RefCell<Game> = RefCell::new(Game::new());
 }
 
 #[no_mangle]
@@ -45,46 +397,131 @@ pub extern "C" fn TIC() {
 fn tic() -> Result<(), Tic80Error> {
     GAME.with(|game| {
         let mut game = game.borrow_mut();
+        let game = &mut *game;
         game.tic += 1;
-
-        if Btnp::default().id(0).hold(6).period(30).btnp() {
-            trace("btn 0\0", None);
-            game.player.y -= 16;
+        if game.banks.begin_frame().contains(SyncMask::MAP) {
+            game.map_loaded()?;
         }
+        game.debug_replay_keys();
+        game.replay.update();
+        game.actions.update();
 
-        if Btnp::default().id(1).hold(6).period(30).btnp() {
-            trace("btn 1\0", None);
-            game.player.y += 16;
+        match &mut game.screen {
+            Screen::Title(picker) => {
+                let mut picker = std::mem::take(picker);
+                let chosen = picker.update(&game.actions, &mut game.saves);
+                picker.draw(&game.actions, &game.saves, "WIZARD'S TOWER");
+                game.screen = Screen::Title(picker);
+                match chosen {
+                    Some(slot) if game.saves.is_empty(slot) => {
+                        game.screen = Screen::NameEntry {
+                            input: TextInput::new(NAME_LEN),
+                            slot,
+                        };
+                    }
+                    Some(slot) => game.start(slot)?,
+                    None => {}
+                }
+                return Ok(());
+            }
+            Screen::NameEntry { input, slot } => {
+                let slot = *slot;
+                let event = input.update();
+                cls(0);
+                Print::default()
+                    .x(72)
+                    .y(44)
+                    .color(12)
+                    .print("NAME YOUR WIZARD\0");
+                rectb(80, 58, NAME_LEN as i32 * 6 + 6, 13, 13);
+                input.draw(83, 61, 12);
+                Print::default()
+                    .x(46)
+                    .y(80)
+                    .color(13)
+                    .smallfont(true)
+                    .print("RETURN: START   TAB: BACK\0");
+                match event {
+                    Some(TextInputEvent::Submit) if !input.text().trim().is_empty() => {
+                        let name = input.text().trim().to_string();
+                        game.new_game(slot, &name)?;
+                    }
+                    Some(TextInputEvent::Cancel) => {
+                        game.screen = Screen::Title(SlotPicker::new());
+                    }
+                    _ => {}
+                }
+                return Ok(());
+            }
+            Screen::Options { menu, slot } => {
+                let slot = *slot;
+                let mut menu = std::mem::take(menu);
+                let closed = menu.update(&mut game.actions);
+                menu.draw(&game.actions);
+                game.screen = if closed {
+                    let bindings = game.actions.bindings().clone();
+                    game.saves.save_settings(bindings)?;
+                    Screen::Playing { slot }
+                } else {
+                    Screen::Options { menu, slot }
+                };
+                return Ok(());
+            }
+            Screen::Playing { slot } => {
+                let slot = *slot;
+                if game.actions.just_pressed(Action::Pause) && !game.rooms.is_active() {
+                    game.screen = Screen::Options {
+                        menu: RebindMenu::new(),
+                        slot,
+                    };
+                    return Ok(());
+                }
+            }
         }
 
-        if Btnp::default().id(2).hold(6).period(30).btnp() {
-            trace("btn 2\0", None);
-            game.player.x -= 16;
+        // Gameplay is frozen while moving between rooms. The room check
+        // comes after moving, so a transition starts on the frame the
+        // player crosses over.
+        if !game.rooms.is_active() {
+            game.play()?;
         }
-
-        if Btnp::default().id(3).hold(6).period(30).btnp() {
-            trace("btn 3\0", None);
-            game.player.x += 16;
+        let (x, y) = game.follow_player();
+        if !game.rooms.update(&mut game.camera, x, y) {
+            game.camera.update();
         }
-    });
+        let camera = &game.camera;
 
-    cls(13);
-    map_default();
+        cls(13);
 
-    GAME.with(|game| {
-        let game = game.borrow();
+        camera.layers_below(&game.map);
+
+        let mut player = Spr::default();
+        player.transparent_color(14_u8).width(2).height(2);
+        camera.spr(&player, 1 + game.tic % 60 / 30 * 2, game.player.x, game.player.y);
+
+        camera.print(&Print::default(), "HELLO WORLD FROM RUST!\0", 84, 84);
+
+        camera.layers_above(&game.map);
 
-        Spr::default()
-            .transparent_color(14_u8)
-            .width(2)
-            .height(2)
-            .spr(1 + game.tic % 60 / 30 * 2, game.player.x, game.player.y);
+        game.stroke.draw(if game.record_runes { 2 } else { 12 });
 
         Print::default()
-            .x(84)
-            .y(84)
-            .print("HELLO WORLD FROM RUST!\0");
-    });
+            .x(2)
+            .y(2)
+            .color(12)
+            .print(format!("{}  FLOOR {}\0", game.name, game.meta.floor));
+
+        if let Some((spell, cast_at)) = game.last_spell {
+            if game.tic - cast_at < 60 {
+                camera.print(
+                    Print::default().color(4),
+                    format!("{}!\0", spell.name()),
+                    game.player.x,
+                    game.player.y - 8,
+                );
+            }
+        }
 
-    Ok(())
+        Ok(())
+    })
 }
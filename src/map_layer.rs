@@ -0,0 +1,273 @@
+use heapless::Vec as Vector;
+
+use crate::tic80::*;
+use crate::tic80_error::Tic80Error;
+
+/// Size of a map tile in pixels.
+pub const TILE_SIZE: i32 = 8;
+/// Number of map tiles needed to cover the screen with one tile of slack for
+/// sub-tile scrolling.
+pub const SCREEN_TILES_W: i32 = WIDTH as i32 / TILE_SIZE + 1;
+pub const SCREEN_TILES_H: i32 = HEIGHT as i32 / TILE_SIZE + 1;
+
+/// How a layer takes part in drawing and collision.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LayerKind {
+    Background,
+    Decoration,
+    Foreground,
+    /// Only queried for collision, never drawn.
+    Collision,
+}
+
+/// A rectangular region of MAP, in tiles, treated as one layer.
+/// Every layer's top-left tile is world position (0, 0).
+#[derive(Clone)]
+pub struct MapLayer {
+    pub kind: LayerKind,
+    pub x: i32,
+    pub y: i32,
+    pub w: i32,
+    pub h: i32,
+    pub transparent_colors: Vector<u8, 15>,
+    /// Scroll speed relative to the view, 1.0 moves with the world.
+    pub parallax_x: f32,
+    pub parallax_y: f32,
+}
+
+impl MapLayer {
+    pub fn new(kind: LayerKind, x: i32, y: i32, w: i32, h: i32) -> Self {
+        Self {
+            kind,
+            x,
+            y,
+            w,
+            h,
+            transparent_colors: Vector::new(),
+            parallax_x: 1.0,
+            parallax_y: 1.0,
+        }
+    }
+
+    /// Add to the list of a transparent colors, at most 15.
+    pub fn transparent_color(mut self, value: u8) -> Result<Self, Tic80Error> {
+        let len = self.transparent_colors.capacity();
+        self.transparent_colors
+            .push(value)
+            .map_err(|_| Tic80Error::OutOfRange { index: len, len })?;
+        Ok(self)
+    }
+
+    pub fn parallax(mut self, x: f32, y: f32) -> Self {
+        self.parallax_x = x;
+        self.parallax_y = y;
+        self
+    }
+
+    /// Returns the tile id at world tile coordinates, or `None` outside the layer.
+    pub fn tile_at(&self, tx: i32, ty: i32) -> Option<i32> {
+        if tx < 0 || ty < 0 || tx >= self.w || ty >= self.h {
+            return None;
+        }
+        Some(mget(self.x + tx, self.y + ty))
+    }
+
+    /// Draw the part of the layer visible from the world pixel position
+    /// `view_x`, `view_y` (the screen's top-left corner).
+    pub fn draw(&self, view_x: i32, view_y: i32) {
+        if self.kind == LayerKind::Collision {
+            return;
+        }
+        let px = (view_x as f32 * self.parallax_x) as i32;
+        let py = (view_y as f32 * self.parallax_y) as i32;
+        let Some(area) = visible_area(self.w, self.h, px, py) else {
+            return;
+        };
+        let mut map = Map::default();
+        map.x(self.x + area.x)
+            .y(self.y + area.y)
+            .w(area.w)
+            .h(area.h)
+            .sx(area.sx)
+            .sy(area.sy);
+        for &color in self.transparent_colors.iter() {
+            map.transparent_color(color);
+        }
+        map.map();
+    }
+}
+
+/// The tiles of a `w` x `h` region that are on screen when the screen's
+/// top-left corner is at pixel `px`, `py`, and where to draw them.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct VisibleArea {
+    pub x: i32,
+    pub y: i32,
+    pub w: i32,
+    pub h: i32,
+    pub sx: i32,
+    pub sy: i32,
+}
+
+/// Returns the visible tile rectangle and sub-tile screen offset for a region,
+/// or `None` when the region is entirely off screen.
+pub fn visible_area(w: i32, h: i32, px: i32, py: i32) -> Option<VisibleArea> {
+    let first_x = px.div_euclid(TILE_SIZE);
+    let first_y = py.div_euclid(TILE_SIZE);
+    let x0 = first_x.max(0);
+    let y0 = first_y.max(0);
+    let x1 = (first_x + SCREEN_TILES_W).min(w);
+    let y1 = (first_y + SCREEN_TILES_H).min(h);
+    if x1 <= x0 || y1 <= y0 {
+        return None;
+    }
+    Some(VisibleArea {
+        x: x0,
+        y: y0,
+        w: x1 - x0,
+        h: y1 - y0,
+        sx: x0 * TILE_SIZE - px,
+        sy: y0 * TILE_SIZE - py,
+    })
+}
+
+/// Layers sharing world coordinates, drawn in insertion order.
+pub struct LayeredMap<const N: usize> {
+    layers: Vector<MapLayer, N>,
+}
+
+impl<const N: usize> LayeredMap<N> {
+    pub fn new() -> Self {
+        Self {
+            layers: Vector::new(),
+        }
+    }
+
+    /// Add a layer on top of the existing ones, at most `N`.
+    pub fn with_layer(mut self, layer: MapLayer) -> Result<Self, Tic80Error> {
+        self.layers
+            .push(layer)
+            .map_err(|_| Tic80Error::OutOfRange { index: N, len: N })?;
+        Ok(self)
+    }
+
+    pub fn layers(&self) -> &[MapLayer] {
+        &self.layers
+    }
+
+    pub fn layers_mut(&mut self) -> &mut [MapLayer] {
+        &mut self.layers
+    }
+
+    /// Draw every layer of `kind`, in order.
+    pub fn draw_kind(&self, kind: LayerKind, view_x: i32, view_y: i32) {
+        for layer in self.layers.iter().filter(|l| l.kind == kind) {
+            layer.draw(view_x, view_y);
+        }
+    }
+
+    /// Draw every renderable layer in order.
+    pub fn draw(&self, view_x: i32, view_y: i32) {
+        for layer in self.layers.iter() {
+            layer.draw(view_x, view_y);
+        }
+    }
+
+    /// Draw the layers below the sprites, everything up to the first foreground layer.
+    pub fn draw_below(&self, view_x: i32, view_y: i32) {
+        for layer in self
+            .layers
+            .iter()
+            .take_while(|l| l.kind != LayerKind::Foreground)
+        {
+            layer.draw(view_x, view_y);
+        }
+    }
+
+    /// Draw the layers above the sprites, from the first foreground layer on.
+    pub fn draw_above(&self, view_x: i32, view_y: i32) {
+        for layer in self
+            .layers
+            .iter()
+            .skip_while(|l| l.kind != LayerKind::Foreground)
+        {
+            layer.draw(view_x, view_y);
+        }
+    }
+
+    /// Returns the collision tile at world tile coordinates, `None` when there
+    /// is no collision layer or the position is outside it.
+    pub fn collision_at(&self, tx: i32, ty: i32) -> Option<i32> {
+        self.layers
+            .iter()
+            .find(|l| l.kind == LayerKind::Collision)
+            .and_then(|l| l.tile_at(tx, ty))
+    }
+}
+
+impl<const N: usize> Default for LayeredMap<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn visible_area_at_origin_covers_the_screen() {
+        let area = visible_area(100, 100, 0, 0).unwrap();
+        assert_eq!(
+            area,
+            VisibleArea {
+                x: 0,
+                y: 0,
+                w: SCREEN_TILES_W,
+                h: SCREEN_TILES_H,
+                sx: 0,
+                sy: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn visible_area_keeps_the_sub_tile_offset() {
+        let area = visible_area(100, 100, 20, 13).unwrap();
+        assert_eq!((area.x, area.y), (2, 1));
+        assert_eq!((area.sx, area.sy), (-4, -5));
+    }
+
+    #[test]
+    fn visible_area_clips_to_the_region() {
+        // Scrolled left of the region, only its first tiles show.
+        let area = visible_area(10, 10, -200, -8).unwrap();
+        assert_eq!((area.x, area.y, area.w, area.h), (0, 0, 6, 10));
+        assert_eq!((area.sx, area.sy), (200, 8));
+    }
+
+    #[test]
+    fn visible_area_off_screen_is_none() {
+        assert_eq!(visible_area(10, 10, 80, 0), None);
+        assert_eq!(visible_area(10, 10, -(WIDTH as i32) - TILE_SIZE, 0), None);
+    }
+
+    #[test]
+    fn layers_past_capacity_are_an_error() {
+        let layer = MapLayer::new(LayerKind::Background, 0, 0, 30, 17);
+        let map = LayeredMap::<1>::new().with_layer(layer.clone()).unwrap();
+        assert!(matches!(
+            map.with_layer(layer),
+            Err(Tic80Error::OutOfRange { index: 1, len: 1 })
+        ));
+    }
+
+    #[test]
+    fn transparent_colors_past_capacity_are_an_error() {
+        let mut layer = MapLayer::new(LayerKind::Background, 0, 0, 30, 17);
+        for color in 0..15 {
+            layer = layer.transparent_color(color).unwrap();
+        }
+        assert!(layer.transparent_color(15).is_err());
+    }
+}
@@ -0,0 +1,343 @@
+use crate::bitpack::BitPack;
+use crate::map_layer::TILE_SIZE;
+use crate::tic80::*;
+
+/// Frames allowed between two clicks of a double click.
+pub const DOUBLE_CLICK_FRAMES: u32 = 20;
+/// Distance in pixels the pointer moves with a button held before it's a
+/// drag instead of a click.
+pub const DRAG_THRESHOLD: i32 = 3;
+
+#[derive(BitPack, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MouseButton {
+    Left,
+    Middle,
+    Right,
+}
+
+impl MouseButton {
+    pub const ALL: [MouseButton; 3] = [MouseButton::Left, MouseButton::Middle, MouseButton::Right];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// A drag with one button, in screen coordinates.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Drag {
+    pub button: MouseButton,
+    pub start_x: i32,
+    pub start_y: i32,
+    pub x: i32,
+    pub y: i32,
+    /// Movement since the previous frame.
+    pub dx: i32,
+    pub dy: i32,
+}
+
+impl Drag {
+    /// Movement since the drag started.
+    pub fn delta(&self) -> (i32, i32) {
+        (self.x - self.start_x, self.y - self.start_y)
+    }
+}
+
+#[derive(Clone, Copy, Default, Debug)]
+struct ButtonState {
+    down: bool,
+    was_down: bool,
+    press_x: i32,
+    press_y: i32,
+    /// Moved past the drag threshold since it was pressed.
+    dragged: bool,
+    clicked: bool,
+    double_clicked: bool,
+    last_click: Option<u32>,
+}
+
+/// Mouse state with history. Call [`Mouse::update`] once per frame, then
+/// query presses, clicks, drags and scrolling for that frame.
+#[derive(Clone, Debug, Default)]
+pub struct Mouse {
+    pub x: i32,
+    pub y: i32,
+    frame: u32,
+    prev_x: i32,
+    prev_y: i32,
+    buttons: [ButtonState; 3],
+    scroll: (i32, i32),
+    scroll_total: (i32, i32),
+    drag: Option<Drag>,
+    drag_started: bool,
+    drag_ended: Option<Drag>,
+}
+
+impl Mouse {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read the mouse for this frame.
+    pub fn update(&mut self) {
+        let mut data = MouseData::default();
+        mouse(&mut data);
+        self.update_with(data);
+    }
+
+    /// Advance one frame with the given state, e.g. from a recording.
+    pub fn update_with(&mut self, data: MouseData) {
+        self.frame += 1;
+        self.prev_x = self.x;
+        self.prev_y = self.y;
+        self.x = data.x.into();
+        self.y = data.y.into();
+        self.scroll = (data.scrollx.into(), data.scrolly.into());
+        self.scroll_total.0 += self.scroll.0;
+        self.scroll_total.1 += self.scroll.1;
+        self.drag_started = false;
+        self.drag_ended = None;
+
+        let down = [data.left, data.middle, data.right];
+        for button in MouseButton::ALL {
+            self.update_button(button, down[button.index()]);
+        }
+
+        if let Some(drag) = &mut self.drag {
+            drag.dx = self.x - drag.x;
+            drag.dy = self.y - drag.y;
+            drag.x = self.x;
+            drag.y = self.y;
+        }
+    }
+
+    fn update_button(&mut self, button: MouseButton, down: bool) {
+        let (x, y, frame) = (self.x, self.y, self.frame);
+        let state = &mut self.buttons[button.index()];
+        state.was_down = state.down;
+        state.down = down;
+        state.clicked = false;
+        state.double_clicked = false;
+
+        if down && !state.was_down {
+            state.press_x = x;
+            state.press_y = y;
+            state.dragged = false;
+        }
+
+        if down && !state.dragged {
+            let moved = (x - state.press_x).abs().max((y - state.press_y).abs());
+            if moved > DRAG_THRESHOLD {
+                state.dragged = true;
+                if self.drag.is_none() {
+                    self.drag = Some(Drag {
+                        button,
+                        start_x: state.press_x,
+                        start_y: state.press_y,
+                        x: self.prev_x,
+                        y: self.prev_y,
+                        dx: 0,
+                        dy: 0,
+                    });
+                    self.drag_started = true;
+                }
+            }
+        }
+
+        if !down && state.was_down {
+            if state.dragged {
+                if self.drag.is_some_and(|drag| drag.button == button) {
+                    self.drag_ended = self.drag.take().map(|mut drag| {
+                        drag.dx = x - drag.x;
+                        drag.dy = y - drag.y;
+                        drag.x = x;
+                        drag.y = y;
+                        drag
+                    });
+                }
+            } else {
+                state.clicked = true;
+                state.double_clicked = state
+                    .last_click
+                    .is_some_and(|last| frame - last <= DOUBLE_CLICK_FRAMES);
+                // A third click starts a new pair.
+                state.last_click = if state.double_clicked {
+                    None
+                } else {
+                    Some(frame)
+                };
+            }
+        }
+    }
+
+    pub fn down(&self, button: MouseButton) -> bool {
+        self.buttons[button.index()].down
+    }
+
+    pub fn just_pressed(&self, button: MouseButton) -> bool {
+        let state = &self.buttons[button.index()];
+        state.down && !state.was_down
+    }
+
+    pub fn just_released(&self, button: MouseButton) -> bool {
+        let state = &self.buttons[button.index()];
+        !state.down && state.was_down
+    }
+
+    /// Released this frame without having been dragged.
+    pub fn clicked(&self, button: MouseButton) -> bool {
+        self.buttons[button.index()].clicked
+    }
+
+    /// The second of two clicks within [`DOUBLE_CLICK_FRAMES`]. The first
+    /// click is still reported by [`Mouse::clicked`].
+    pub fn double_clicked(&self, button: MouseButton) -> bool {
+        self.buttons[button.index()].double_clicked
+    }
+
+    /// The drag in progress, including the frame it started.
+    pub fn drag(&self) -> Option<Drag> {
+        self.drag
+    }
+
+    /// The drag that began this frame.
+    pub fn drag_start(&self) -> Option<Drag> {
+        self.drag.filter(|_| self.drag_started)
+    }
+
+    /// The drag whose button was released this frame.
+    pub fn drag_end(&self) -> Option<Drag> {
+        self.drag_ended
+    }
+
+    /// Scrolling this frame.
+    pub fn scroll(&self) -> (i32, i32) {
+        self.scroll
+    }
+
+    /// Scrolling since the last call, for consumers that don't check every
+    /// frame.
+    pub fn take_scroll(&mut self) -> (i32, i32) {
+        std::mem::take(&mut self.scroll_total)
+    }
+
+    /// The screen tile under the pointer.
+    pub fn tile(&self) -> (i32, i32) {
+        (self.x.div_euclid(TILE_SIZE), self.y.div_euclid(TILE_SIZE))
+    }
+
+    /// The pointer in world coordinates, with the view's top left corner at
+    /// `(view_x, view_y)`.
+    pub fn world(&self, view_x: i32, view_y: i32) -> (i32, i32) {
+        (self.x + view_x, self.y + view_y)
+    }
+
+    /// The map tile under the pointer, with the view's top left corner at
+    /// `(view_x, view_y)` in pixels.
+    pub fn world_tile(&self, view_x: i32, view_y: i32) -> (i32, i32) {
+        let (x, y) = self.world(view_x, view_y);
+        (x.div_euclid(TILE_SIZE), y.div_euclid(TILE_SIZE))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(x: i16, y: i16, left: bool) -> MouseData {
+        MouseData {
+            x,
+            y,
+            left,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn press_and_release_is_a_click() {
+        let mut mouse = Mouse::new();
+        mouse.update_with(at(10, 10, true));
+        assert!(mouse.just_pressed(MouseButton::Left));
+        assert!(mouse.down(MouseButton::Left));
+        mouse.update_with(at(12, 11, false));
+        assert!(mouse.just_released(MouseButton::Left));
+        assert!(mouse.clicked(MouseButton::Left));
+        assert!(!mouse.double_clicked(MouseButton::Left));
+        assert_eq!(mouse.drag_end(), None);
+    }
+
+    #[test]
+    fn quick_clicks_double_click() {
+        let mut mouse = Mouse::new();
+        for _ in 0..2 {
+            mouse.update_with(at(0, 0, true));
+            mouse.update_with(at(0, 0, false));
+        }
+        assert!(mouse.double_clicked(MouseButton::Left));
+        // The third click starts a new pair.
+        mouse.update_with(at(0, 0, true));
+        mouse.update_with(at(0, 0, false));
+        assert!(!mouse.double_clicked(MouseButton::Left));
+    }
+
+    #[test]
+    fn slow_clicks_are_single() {
+        let mut mouse = Mouse::new();
+        mouse.update_with(at(0, 0, true));
+        mouse.update_with(at(0, 0, false));
+        for _ in 0..DOUBLE_CLICK_FRAMES {
+            mouse.update_with(at(0, 0, false));
+        }
+        mouse.update_with(at(0, 0, true));
+        mouse.update_with(at(0, 0, false));
+        assert!(mouse.clicked(MouseButton::Left));
+        assert!(!mouse.double_clicked(MouseButton::Left));
+    }
+
+    #[test]
+    fn moving_past_the_threshold_drags() {
+        let mut mouse = Mouse::new();
+        mouse.update_with(at(10, 10, true));
+        mouse.update_with(at(13, 10, true));
+        assert_eq!(mouse.drag(), None);
+        mouse.update_with(at(15, 12, true));
+        let drag = mouse.drag_start().unwrap();
+        assert_eq!((drag.start_x, drag.start_y), (10, 10));
+        assert_eq!((drag.x, drag.y), (15, 12));
+        assert_eq!((drag.dx, drag.dy), (2, 2));
+
+        mouse.update_with(at(20, 12, true));
+        assert_eq!(mouse.drag_start(), None);
+        assert_eq!(mouse.drag().unwrap().delta(), (10, 2));
+
+        mouse.update_with(at(21, 14, false));
+        let end = mouse.drag_end().unwrap();
+        assert_eq!(end.delta(), (11, 4));
+        assert_eq!((end.dx, end.dy), (1, 2));
+        assert!(!mouse.clicked(MouseButton::Left));
+        assert_eq!(mouse.drag(), None);
+    }
+
+    #[test]
+    fn scrolling_accumulates() {
+        let mut mouse = Mouse::new();
+        let scroll = MouseData {
+            scrolly: -1,
+            ..Default::default()
+        };
+        mouse.update_with(scroll);
+        mouse.update_with(scroll);
+        assert_eq!(mouse.scroll(), (0, -1));
+        assert_eq!(mouse.take_scroll(), (0, -2));
+        assert_eq!(mouse.take_scroll(), (0, 0));
+    }
+
+    #[test]
+    fn tiles_under_the_pointer() {
+        let mut mouse = Mouse::new();
+        mouse.update_with(at(17, 9, false));
+        assert_eq!(mouse.tile(), (2, 1));
+        assert_eq!(mouse.world(-20, 100), (-3, 109));
+        assert_eq!(mouse.world_tile(-20, 100), (-1, 13));
+    }
+}
@@ -0,0 +1,412 @@
+use crate::tic80::*;
+use crate::tic80_error::Tic80Error;
+
+pub const PATTERN_COUNT: usize = 60;
+pub const PATTERN_ROWS: usize = 64;
+pub const PATTERN_SIZE: usize = PATTERN_ROWS * ROW_SIZE;
+pub const TRACK_COUNT: usize = 8;
+pub const TRACK_FRAMES: usize = 16;
+pub const TRACK_CHANNELS: usize = 4;
+pub const TRACK_SIZE: usize = 51;
+pub const DEFAULT_TEMPO: i32 = 150;
+pub const DEFAULT_SPEED: i32 = 6;
+const ROW_SIZE: usize = 3;
+const FRAME_SIZE: usize = 3;
+/// Stored note values below this are special, `NOTE_START` is C.
+const NOTE_OFF: u8 = 1;
+const NOTE_START: u8 = 4;
+
+/// The note column of a pattern row.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum RowNote {
+    #[default]
+    Empty,
+    /// Stop the channel.
+    Off,
+    /// Note 0 = C .. 11 = B, octave 0..7.
+    Play { note: u8, octave: u8 },
+}
+
+/// The effect column of a pattern row.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum Command {
+    #[default]
+    Empty,
+    /// M: master volume, left x and right y.
+    Volume,
+    /// C: chord, x and y semitones above the note.
+    Chord,
+    /// J: jump to frame x row y.
+    Jump,
+    /// S: slide to the note over xy ticks.
+    Slide,
+    /// P: fine pitch.
+    Pitch,
+    /// V: vibrato, period x and depth y.
+    Vibrato,
+    /// D: delay the note by xy ticks.
+    Delay,
+}
+
+impl Command {
+    fn from_bits(bits: u8) -> Self {
+        match bits & 0x07 {
+            1 => Command::Volume,
+            2 => Command::Chord,
+            3 => Command::Jump,
+            4 => Command::Slide,
+            5 => Command::Pitch,
+            6 => Command::Vibrato,
+            7 => Command::Delay,
+            _ => Command::Empty,
+        }
+    }
+
+    fn to_bits(self) -> u8 {
+        match self {
+            Command::Empty => 0,
+            Command::Volume => 1,
+            Command::Chord => 2,
+            Command::Jump => 3,
+            Command::Slide => 4,
+            Command::Pitch => 5,
+            Command::Vibrato => 6,
+            Command::Delay => 7,
+        }
+    }
+}
+
+/// One row of a tracker pattern.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct PatternRow {
+    pub note: RowNote,
+    /// Sound effect used as the instrument, 0..63.
+    pub sfx: u8,
+    pub command: Command,
+    /// Command arguments, 0..15 each.
+    pub x: u8,
+    pub y: u8,
+}
+
+impl PatternRow {
+    pub fn play(note: u8, octave: u8, sfx: u8) -> Self {
+        Self {
+            note: RowNote::Play { note, octave },
+            sfx,
+            ..Default::default()
+        }
+    }
+
+    pub fn off() -> Self {
+        Self {
+            note: RowNote::Off,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_command(mut self, command: Command, x: u8, y: u8) -> Self {
+        self.command = command;
+        self.x = x;
+        self.y = y;
+        self
+    }
+
+    /// Unpack a row, stored as note:4 x:4 | y:4 command:3 sfx_high:1 | sfx_low:5 octave:3.
+    pub fn from_bytes(bytes: &[u8; ROW_SIZE]) -> Self {
+        let stored_note = bytes[0] & 0x0f;
+        let octave = bytes[2] >> 5;
+        let note = match stored_note {
+            NOTE_OFF => RowNote::Off,
+            n if n >= NOTE_START => RowNote::Play {
+                note: n - NOTE_START,
+                octave,
+            },
+            _ => RowNote::Empty,
+        };
+        Self {
+            note,
+            x: bytes[0] >> 4,
+            y: bytes[1] & 0x0f,
+            command: Command::from_bits(bytes[1] >> 4),
+            sfx: ((bytes[1] >> 7) << 5) | (bytes[2] & 0x1f),
+        }
+    }
+
+    pub fn to_bytes(self) -> [u8; ROW_SIZE] {
+        let (stored_note, octave) = match self.note {
+            RowNote::Empty => (0, 0),
+            RowNote::Off => (NOTE_OFF, 0),
+            RowNote::Play { note, octave } => (NOTE_START + note % 12, octave & 0x07),
+        };
+        let sfx = self.sfx & 0x3f;
+        [
+            stored_note | (self.x << 4),
+            (self.y & 0x0f) | (self.command.to_bits() << 4) | ((sfx >> 5) << 7),
+            (sfx & 0x1f) | (octave << 5),
+        ]
+    }
+}
+
+/// A tracker pattern as stored in `MUSIC_PATTERNS`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Pattern {
+    pub rows: [PatternRow; PATTERN_ROWS],
+}
+
+impl Default for Pattern {
+    fn default() -> Self {
+        Self {
+            rows: [PatternRow::default(); PATTERN_ROWS],
+        }
+    }
+}
+
+impl Pattern {
+    pub fn from_bytes(bytes: &[u8; PATTERN_SIZE]) -> Self {
+        let mut pattern = Self::default();
+        for (row, data) in pattern.rows.iter_mut().zip(bytes.chunks(ROW_SIZE)) {
+            *row = PatternRow::from_bytes(&[data[0], data[1], data[2]]);
+        }
+        pattern
+    }
+
+    pub fn to_bytes(&self) -> [u8; PATTERN_SIZE] {
+        let mut bytes = [0; PATTERN_SIZE];
+        for (row, data) in self.rows.iter().zip(bytes.chunks_mut(ROW_SIZE)) {
+            data.copy_from_slice(&row.to_bytes());
+        }
+        bytes
+    }
+
+    /// Read pattern `id` (0..60) from `MUSIC_PATTERNS`.
+    pub fn read(id: usize) -> Result<Self, Tic80Error> {
+        let offset = pattern_offset(id)?;
+        let mut bytes = [0; PATTERN_SIZE];
+        unsafe {
+            let patterns = &*MUSIC_PATTERNS;
+            bytes.copy_from_slice(&patterns[offset..offset + PATTERN_SIZE]);
+        }
+        Ok(Self::from_bytes(&bytes))
+    }
+
+    /// Write to pattern `id` (0..60) in `MUSIC_PATTERNS`.
+    pub fn write(&self, id: usize) -> Result<(), Tic80Error> {
+        let offset = pattern_offset(id)?;
+        unsafe {
+            let patterns = &mut *MUSIC_PATTERNS;
+            patterns[offset..offset + PATTERN_SIZE].copy_from_slice(&self.to_bytes());
+        }
+        Ok(())
+    }
+
+    /// Read one row without decoding the whole pattern.
+    pub fn read_row(id: usize, row: usize) -> Result<PatternRow, Tic80Error> {
+        let offset = row_offset(id, row)?;
+        unsafe {
+            let patterns = &*MUSIC_PATTERNS;
+            Ok(PatternRow::from_bytes(&[
+                patterns[offset],
+                patterns[offset + 1],
+                patterns[offset + 2],
+            ]))
+        }
+    }
+
+    /// Write one row without encoding the whole pattern.
+    pub fn write_row(id: usize, row: usize, value: PatternRow) -> Result<(), Tic80Error> {
+        let offset = row_offset(id, row)?;
+        unsafe {
+            let patterns = &mut *MUSIC_PATTERNS;
+            patterns[offset..offset + ROW_SIZE].copy_from_slice(&value.to_bytes());
+        }
+        Ok(())
+    }
+}
+
+/// A track as stored in `MUSIC_TRACKS`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Track {
+    /// Pattern index (0..60) per channel for each frame, `None` is silent.
+    pub frames: [[Option<u8>; TRACK_CHANNELS]; TRACK_FRAMES],
+    /// Beats per minute, 150 by default.
+    pub tempo: i32,
+    /// Ticks per row, 6 by default.
+    pub speed: i32,
+    /// Rows played per pattern, 1..=64.
+    pub rows: u8,
+}
+
+impl Default for Track {
+    fn default() -> Self {
+        Self {
+            frames: [[None; TRACK_CHANNELS]; TRACK_FRAMES],
+            tempo: DEFAULT_TEMPO,
+            speed: DEFAULT_SPEED,
+            rows: PATTERN_ROWS as u8,
+        }
+    }
+}
+
+impl Track {
+    /// Unpack a track. Each frame is 24 bits holding four 6 bit pattern ids,
+    /// where 0 is empty and `n` is pattern `n - 1`. Tempo, rows and speed are
+    /// stored as differences from their defaults.
+    pub fn from_bytes(bytes: &[u8; TRACK_SIZE]) -> Self {
+        let mut track = Self::default();
+        for (frame, data) in track.frames.iter_mut().zip(bytes.chunks(FRAME_SIZE)) {
+            let bits = data[0] as u32 | (data[1] as u32) << 8 | (data[2] as u32) << 16;
+            for (channel, pattern) in frame.iter_mut().enumerate() {
+                let id = (bits >> (channel * 6)) & 0x3f;
+                *pattern = if id == 0 { None } else { Some(id as u8 - 1) };
+            }
+        }
+        let extra = TRACK_FRAMES * FRAME_SIZE;
+        track.tempo = DEFAULT_TEMPO + bytes[extra] as i8 as i32;
+        track.rows = PATTERN_ROWS as u8 - bytes[extra + 1].min(PATTERN_ROWS as u8 - 1);
+        track.speed = DEFAULT_SPEED + bytes[extra + 2] as i8 as i32;
+        track
+    }
+
+    pub fn to_bytes(&self) -> [u8; TRACK_SIZE] {
+        let mut bytes = [0; TRACK_SIZE];
+        for (frame, data) in self.frames.iter().zip(bytes.chunks_mut(FRAME_SIZE)) {
+            let mut bits = 0u32;
+            for (channel, pattern) in frame.iter().enumerate() {
+                let id = pattern.map_or(0, |p| (p as u32 % PATTERN_COUNT as u32) + 1);
+                bits |= id << (channel * 6);
+            }
+            data.copy_from_slice(&bits.to_le_bytes()[..FRAME_SIZE]);
+        }
+        let extra = TRACK_FRAMES * FRAME_SIZE;
+        bytes[extra] = (self.tempo - DEFAULT_TEMPO).clamp(-128, 127) as i8 as u8;
+        bytes[extra + 1] = PATTERN_ROWS as u8 - self.rows.clamp(1, PATTERN_ROWS as u8);
+        bytes[extra + 2] = (self.speed - DEFAULT_SPEED).clamp(-128, 127) as i8 as u8;
+        bytes
+    }
+
+    /// Read track `id` (0..8) from `MUSIC_TRACKS`.
+    pub fn read(id: usize) -> Result<Self, Tic80Error> {
+        let offset = track_offset(id)?;
+        let mut bytes = [0; TRACK_SIZE];
+        unsafe {
+            let tracks = &*MUSIC_TRACKS;
+            bytes.copy_from_slice(&tracks[offset..offset + TRACK_SIZE]);
+        }
+        Ok(Self::from_bytes(&bytes))
+    }
+
+    /// Write to track `id` (0..8) in `MUSIC_TRACKS`.
+    pub fn write(&self, id: usize) -> Result<(), Tic80Error> {
+        let offset = track_offset(id)?;
+        unsafe {
+            let tracks = &mut *MUSIC_TRACKS;
+            tracks[offset..offset + TRACK_SIZE].copy_from_slice(&self.to_bytes());
+        }
+        Ok(())
+    }
+}
+
+fn pattern_offset(id: usize) -> Result<usize, Tic80Error> {
+    if id >= PATTERN_COUNT {
+        return Err(Tic80Error::OutOfRange {
+            index: id,
+            len: PATTERN_COUNT,
+        });
+    }
+    Ok(id * PATTERN_SIZE)
+}
+
+fn row_offset(id: usize, row: usize) -> Result<usize, Tic80Error> {
+    if row >= PATTERN_ROWS {
+        return Err(Tic80Error::OutOfRange {
+            index: row,
+            len: PATTERN_ROWS,
+        });
+    }
+    Ok(pattern_offset(id)? + row * ROW_SIZE)
+}
+
+fn track_offset(id: usize) -> Result<usize, Tic80Error> {
+    if id >= TRACK_COUNT {
+        return Err(Tic80Error::OutOfRange {
+            index: id,
+            len: TRACK_COUNT,
+        });
+    }
+    Ok(id * TRACK_SIZE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+
+    #[test]
+    fn encodes_a_row() {
+        let row = PatternRow::play(9, 4, 33).with_command(Command::Volume, 2, 3);
+        assert_eq!(row.to_bytes(), [0x2d, 0x93, 0x81]);
+        assert_eq!(PatternRow::from_bytes(&[0x2d, 0x93, 0x81]), row);
+        assert_eq!(PatternRow::off().to_bytes(), [0x01, 0x00, 0x00]);
+        assert_eq!(PatternRow::default().to_bytes(), [0; ROW_SIZE]);
+    }
+
+    #[test]
+    fn rows_round_trip() {
+        let mut rng = Rng::new(5);
+        for _ in 0..200 {
+            let row = PatternRow {
+                note: match rng.range(0, 3) {
+                    0 => RowNote::Empty,
+                    1 => RowNote::Off,
+                    _ => RowNote::Play {
+                        note: rng.range(0, 12) as u8,
+                        octave: rng.range(0, 8) as u8,
+                    },
+                },
+                sfx: rng.range(0, 64) as u8,
+                command: Command::from_bits(rng.range(0, 8) as u8),
+                x: rng.range(0, 16) as u8,
+                y: rng.range(0, 16) as u8,
+            };
+            assert_eq!(PatternRow::from_bytes(&row.to_bytes()), row);
+        }
+    }
+
+    #[test]
+    fn patterns_round_trip() {
+        let mut pattern = Pattern::default();
+        pattern.rows[0] = PatternRow::play(0, 3, 1);
+        pattern.rows[63] = PatternRow::off();
+        let bytes = pattern.to_bytes();
+        assert_eq!(&bytes[..3], &PatternRow::play(0, 3, 1).to_bytes());
+        assert_eq!(Pattern::from_bytes(&bytes), pattern);
+    }
+
+    #[test]
+    fn encodes_a_track() {
+        let mut track = Track {
+            tempo: 120,
+            speed: 3,
+            rows: 32,
+            ..Default::default()
+        };
+        track.frames[0] = [Some(0), None, Some(2), Some(59)];
+        let bytes = track.to_bytes();
+        assert_eq!(&bytes[..3], &[0x01, 0x30, 0xf0]);
+        assert_eq!(&bytes[48..], &[0xe2, 32, 0xfd]);
+        assert_eq!(Track::from_bytes(&bytes), track);
+    }
+
+    #[test]
+    fn default_track_is_all_zero() {
+        assert_eq!(Track::default().to_bytes(), [0; TRACK_SIZE]);
+    }
+
+    #[test]
+    fn offsets_are_checked() {
+        assert_eq!(row_offset(1, 2).unwrap(), PATTERN_SIZE + 2 * ROW_SIZE);
+        assert!(row_offset(0, PATTERN_ROWS).is_err());
+        assert!(pattern_offset(PATTERN_COUNT).is_err());
+        assert!(track_offset(TRACK_COUNT).is_err());
+    }
+}
@@ -0,0 +1,176 @@
+use crate::tic80::*;
+
+/// Music playback position as reported in `SOUND_STATE`.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct MusicStatus {
+    /// Playing track, `None` when stopped.
+    pub track: Option<u8>,
+    pub frame: u8,
+    pub row: u8,
+    pub looping: bool,
+}
+
+impl MusicStatus {
+    /// Read the current state.
+    pub fn read() -> Self {
+        Self::from_bytes(unsafe { *SOUND_STATE })
+    }
+
+    /// The bytes are the track, frame and row as signed values (-1 when
+    /// stopped) followed by a flag byte with the loop bit.
+    pub fn from_bytes(state: [u8; 4]) -> Self {
+        let track = state[0] as i8;
+        Self {
+            track: if track < 0 { None } else { Some(track as u8) },
+            frame: (state[1] as i8).max(0) as u8,
+            row: (state[2] as i8).max(0) as u8,
+            looping: state[3] & 0x01 != 0,
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.track.is_some()
+    }
+}
+
+/// Starts, stops and pauses music and tracks its position for syncing
+/// gameplay to the beat. Call [`MusicPlayer::update`] once per frame.
+#[derive(Default)]
+pub struct MusicPlayer {
+    options: Music,
+    status: MusicStatus,
+    previous: MusicStatus,
+    paused: Option<MusicStatus>,
+}
+
+impl MusicPlayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start playing, the options are kept for [`MusicPlayer::resume`].
+    pub fn play(&mut self, options: &Music) {
+        self.options = options.clone();
+        self.paused = None;
+        options.play();
+    }
+
+    pub fn stop(&mut self) {
+        self.paused = None;
+        Music::stop();
+    }
+
+    /// Stop and remember the position.
+    pub fn pause(&mut self) {
+        let status = MusicStatus::read();
+        if status.is_playing() {
+            self.paused = Some(status);
+            Music::stop();
+        }
+    }
+
+    /// Continue from where [`MusicPlayer::pause`] stopped, with the same options.
+    pub fn resume(&mut self) {
+        if let Some(status) = self.paused.take() {
+            let mut options = self.options.clone();
+            options
+                .track(status.track.unwrap_or(0) as i32)
+                .frame(status.frame as i32)
+                .row(status.row as i32);
+            options.play();
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.is_some()
+    }
+
+    /// Refresh the status from `SOUND_STATE`.
+    pub fn update(&mut self) {
+        self.previous = self.status;
+        self.status = MusicStatus::read();
+    }
+
+    /// Status as of the last [`MusicPlayer::update`].
+    pub fn status(&self) -> MusicStatus {
+        self.status
+    }
+
+    /// Returns the row when playback moved onto a new row since the last update.
+    pub fn row_changed(&self) -> Option<u8> {
+        let changed = self.status.row != self.previous.row
+            || self.status.frame != self.previous.frame
+            || self.status.track != self.previous.track;
+        if self.status.is_playing() && changed {
+            Some(self.status.row)
+        } else {
+            None
+        }
+    }
+
+    /// Returns `true` on the update where a row that is a multiple of
+    /// `rows_per_beat` starts playing.
+    pub fn on_beat(&self, rows_per_beat: u8) -> bool {
+        self.row_changed()
+            .is_some_and(|row| rows_per_beat > 0 && row % rows_per_beat == 0)
+    }
+
+    /// Returns `true` on the update where playback moved to a new frame.
+    pub fn frame_changed(&self) -> bool {
+        self.status.is_playing()
+            && (self.status.frame != self.previous.frame
+                || self.status.track != self.previous.track)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn playing(frame: u8, row: u8) -> MusicStatus {
+        MusicStatus {
+            track: Some(1),
+            frame,
+            row,
+            looping: true,
+        }
+    }
+
+    fn player(previous: MusicStatus, status: MusicStatus) -> MusicPlayer {
+        MusicPlayer {
+            previous,
+            status,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn status_from_sound_state() {
+        assert_eq!(MusicStatus::from_bytes([1, 2, 3, 1]), playing(2, 3));
+        let stopped = MusicStatus::from_bytes([0xff, 0xff, 0xff, 0]);
+        assert_eq!(stopped, MusicStatus::default());
+        assert!(!stopped.is_playing());
+    }
+
+    #[test]
+    fn row_and_beat_changes() {
+        let moved = player(playing(0, 3), playing(0, 4));
+        assert_eq!(moved.row_changed(), Some(4));
+        assert!(moved.on_beat(4));
+        assert!(!moved.on_beat(3));
+        assert!(!moved.on_beat(0));
+        assert!(!moved.frame_changed());
+
+        let still = player(playing(0, 4), playing(0, 4));
+        assert_eq!(still.row_changed(), None);
+        assert!(!still.on_beat(4));
+
+        // Row 0 of the next frame is a new row too.
+        let next = player(playing(0, 0), playing(1, 0));
+        assert_eq!(next.row_changed(), Some(0));
+        assert!(next.frame_changed());
+
+        let stopped = player(playing(0, 3), MusicStatus::default());
+        assert_eq!(stopped.row_changed(), None);
+    }
+}
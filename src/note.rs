@@ -0,0 +1,291 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
+use heapless::Vec as Vector;
+
+use crate::music_data::RowNote;
+use crate::tic80::*;
+use crate::tic80_error::Tic80Error;
+
+pub const MAX_OCTAVE: u8 = 7;
+
+/// Why a note name couldn't be parsed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum NoteParseError {
+    Empty,
+    /// The first character isn't a note letter A to G.
+    InvalidName,
+    /// The octave is missing or not a digit 0..7.
+    InvalidOctave,
+    TrailingCharacters,
+}
+
+impl Display for NoteParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NoteParseError::Empty => write!(f, "empty note"),
+            NoteParseError::InvalidName => write!(f, "note name must be A to G"),
+            NoteParseError::InvalidOctave => write!(f, "octave must be 0 to {}", MAX_OCTAVE),
+            NoteParseError::TrailingCharacters => write!(f, "unexpected characters after note"),
+        }
+    }
+}
+
+/// A pitch as `sfx` and the music patterns take it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Note {
+    /// 0 = C .. 11 = B.
+    pub note: u8,
+    pub octave: u8,
+}
+
+impl Note {
+    /// Parse names like `"C#4"`, `"Eb3"` or `"A-5"` (tracker style natural).
+    /// Usable in constants, see [`note!`].
+    pub const fn parse_const(text: &str) -> Result<Note, NoteParseError> {
+        let bytes = text.as_bytes();
+        if bytes.is_empty() {
+            return Err(NoteParseError::Empty);
+        }
+        let base: i32 = match bytes[0] {
+            b'C' | b'c' => 0,
+            b'D' | b'd' => 2,
+            b'E' | b'e' => 4,
+            b'F' | b'f' => 5,
+            b'G' | b'g' => 7,
+            b'A' | b'a' => 9,
+            b'B' | b'b' => 11,
+            _ => return Err(NoteParseError::InvalidName),
+        };
+        let (accidental, next) = if bytes.len() > 1 {
+            match bytes[1] {
+                b'#' => (1, 2),
+                b'b' => (-1, 2),
+                b'-' => (0, 2),
+                _ => (0, 1),
+            }
+        } else {
+            (0, 1)
+        };
+        if next >= bytes.len() || !bytes[next].is_ascii_digit() {
+            return Err(NoteParseError::InvalidOctave);
+        }
+        if next + 1 < bytes.len() {
+            return Err(NoteParseError::TrailingCharacters);
+        }
+        // Cb and B# cross into the neighbouring octave.
+        let semitone = (bytes[next] - b'0') as i32 * 12 + base + accidental;
+        if semitone < 0 || semitone / 12 > MAX_OCTAVE as i32 {
+            return Err(NoteParseError::InvalidOctave);
+        }
+        Ok(Note {
+            note: (semitone % 12) as u8,
+            octave: (semitone / 12) as u8,
+        })
+    }
+
+    /// Parse a note name at runtime.
+    pub fn parse(text: &str) -> Result<Note, Tic80Error> {
+        Ok(Self::parse_const(text.trim())?)
+    }
+}
+
+impl FromStr for Note {
+    type Err = Tic80Error;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        Self::parse(text)
+    }
+}
+
+impl From<Note> for RowNote {
+    fn from(note: Note) -> Self {
+        RowNote::Play {
+            note: note.note,
+            octave: note.octave,
+        }
+    }
+}
+
+/// A [`Note`] parsed at compile time, an invalid name fails the build.
+///
+/// `Sfx::default().pitch(note!("C#4")).sfx(0);`
+#[macro_export]
+macro_rules! note {
+    ($text:literal) => {{
+        const NOTE: $crate::note::Note = match $crate::note::Note::parse_const($text) {
+            Ok(note) => note,
+            Err(_) => panic!(concat!("invalid note name: ", $text)),
+        };
+        NOTE
+    }};
+}
+
+impl Sfx {
+    /// Set the note and octave from a [`Note`].
+    pub fn pitch(&mut self, note: Note) -> &mut Self {
+        self.note(note.note as i32).octave(note.octave as i32)
+    }
+}
+
+/// A sequence of notes and rests, one per step.
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct Melody {
+    pub steps: Vector<Option<Note>, 64>,
+}
+
+impl Melody {
+    /// Parse space separated note names, `.` is a rest: `"C4 E4 . G4 C5"`.
+    pub fn parse(text: &str) -> Result<Melody, Tic80Error> {
+        let mut steps = Vector::new();
+        for token in text.split_whitespace() {
+            let step = if token == "." {
+                None
+            } else {
+                Some(Note::parse(token)?)
+            };
+            steps.push(step).map_err(|_| Tic80Error::OutOfRange {
+                index: steps.len(),
+                len: steps.capacity(),
+            })?;
+        }
+        Ok(Melody { steps })
+    }
+}
+
+/// Plays a [`Melody`] with one sound effect on one channel, a step every
+/// `step_frames` frames. Call [`MelodyPlayer::update`] once per frame.
+#[derive(Clone, Debug)]
+pub struct MelodyPlayer {
+    melody: Melody,
+    sfx: i32,
+    channel: i32,
+    step_frames: u32,
+    looping: bool,
+    position: usize,
+    timer: u32,
+    playing: bool,
+}
+
+impl MelodyPlayer {
+    pub fn new(melody: Melody, sfx: i32, channel: i32, step_frames: u32) -> Self {
+        Self {
+            melody,
+            sfx,
+            channel,
+            step_frames: step_frames.max(1),
+            looping: false,
+            position: 0,
+            timer: 0,
+            playing: false,
+        }
+    }
+
+    pub fn looping(mut self, looping: bool) -> Self {
+        self.looping = looping;
+        self
+    }
+
+    pub fn play(&mut self) {
+        self.position = 0;
+        self.timer = 0;
+        self.playing = true;
+    }
+
+    pub fn stop(&mut self) {
+        self.playing = false;
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    pub fn update(&mut self) {
+        if !self.playing {
+            return;
+        }
+        if self.timer == 0 {
+            if self.position >= self.melody.steps.len() {
+                if self.looping && !self.melody.steps.is_empty() {
+                    self.position = 0;
+                } else {
+                    self.playing = false;
+                    return;
+                }
+            }
+            if let Some(note) = self.melody.steps[self.position] {
+                let mut sfx = Sfx::default();
+                sfx.pitch(note)
+                    .channel(self.channel)
+                    .duration(self.step_frames as i32);
+                sfx.sfx(self.sfx);
+            }
+            self.position += 1;
+            self.timer = self.step_frames;
+        }
+        self.timer -= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(note: u8, octave: u8) -> Note {
+        Note { note, octave }
+    }
+
+    #[test]
+    fn parses_names() {
+        assert_eq!(Note::parse_const("C4"), Ok(note(0, 4)));
+        assert_eq!(Note::parse_const("c#4"), Ok(note(1, 4)));
+        assert_eq!(Note::parse_const("Eb3"), Ok(note(3, 3)));
+        assert_eq!(Note::parse_const("A-5"), Ok(note(9, 5)));
+        assert_eq!(Note::parse(" B7 ").unwrap(), note(11, 7));
+    }
+
+    #[test]
+    fn accidentals_cross_octaves() {
+        assert_eq!(Note::parse_const("Cb4"), Ok(note(11, 3)));
+        assert_eq!(Note::parse_const("B#3"), Ok(note(0, 4)));
+        assert_eq!(Note::parse_const("Cb0"), Err(NoteParseError::InvalidOctave));
+        assert_eq!(Note::parse_const("B#7"), Err(NoteParseError::InvalidOctave));
+    }
+
+    #[test]
+    fn rejects_bad_names() {
+        assert_eq!(Note::parse_const(""), Err(NoteParseError::Empty));
+        assert_eq!(Note::parse_const("H4"), Err(NoteParseError::InvalidName));
+        assert_eq!(Note::parse_const("C"), Err(NoteParseError::InvalidOctave));
+        assert_eq!(Note::parse_const("C#"), Err(NoteParseError::InvalidOctave));
+        assert_eq!(Note::parse_const("C8"), Err(NoteParseError::InvalidOctave));
+        assert_eq!(
+            Note::parse_const("C44"),
+            Err(NoteParseError::TrailingCharacters)
+        );
+    }
+
+    #[test]
+    fn macro_matches_parse() {
+        const LOW: Note = note!("G2");
+        assert_eq!(LOW, note(7, 2));
+    }
+
+    #[test]
+    fn parses_melodies() {
+        let melody = Melody::parse("C4 E4 . G4").unwrap();
+        assert_eq!(
+            melody.steps.as_slice(),
+            &[Some(note(0, 4)), Some(note(4, 4)), None, Some(note(7, 4))]
+        );
+        assert!(matches!(
+            Melody::parse("C4 X4"),
+            Err(Tic80Error::NoteParseError(NoteParseError::InvalidName))
+        ));
+        let long = vec!["C4"; 65].join(" ");
+        assert!(matches!(
+            Melody::parse(&long),
+            Err(Tic80Error::OutOfRange { index: 64, len: 64 })
+        ));
+    }
+}
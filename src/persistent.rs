@@ -0,0 +1,369 @@
+use std::marker::PhantomData;
+
+use crate::tic80::*;
+
+/// Number of 32 bit slots in persistent memory.
+pub const SLOTS: usize = 256;
+/// Slots taken by the header: magic value, schema version and checksum.
+pub const HEADER_SLOTS: usize = 3;
+const MAGIC_SLOT: usize = 0;
+const VERSION_SLOT: usize = 1;
+const CHECKSUM_SLOT: usize = 2;
+
+/// A value that fits in one 32 bit slot.
+pub trait SlotValue: Sized {
+    fn to_slot(self) -> u32;
+    fn from_slot(value: u32) -> Self;
+}
+
+impl SlotValue for u8 {
+    fn to_slot(self) -> u32 {
+        self.into()
+    }
+    fn from_slot(value: u32) -> Self {
+        value as u8
+    }
+}
+
+impl SlotValue for u16 {
+    fn to_slot(self) -> u32 {
+        self.into()
+    }
+    fn from_slot(value: u32) -> Self {
+        value as u16
+    }
+}
+
+impl SlotValue for u32 {
+    fn to_slot(self) -> u32 {
+        self
+    }
+    fn from_slot(value: u32) -> Self {
+        value
+    }
+}
+
+impl SlotValue for bool {
+    fn to_slot(self) -> u32 {
+        self.into()
+    }
+    fn from_slot(value: u32) -> Self {
+        value != 0
+    }
+}
+
+/// Signed 16.16 fixed point number.
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct Fixed(pub i32);
+
+impl Fixed {
+    pub const ONE: Fixed = Fixed(1 << 16);
+
+    pub fn from_f32(value: f32) -> Self {
+        Fixed((value * 65536.0) as i32)
+    }
+
+    pub fn to_f32(self) -> f32 {
+        self.0 as f32 / 65536.0
+    }
+}
+
+impl SlotValue for Fixed {
+    fn to_slot(self) -> u32 {
+        self.0 as u32
+    }
+    fn from_slot(value: u32) -> Self {
+        Fixed(value as i32)
+    }
+}
+
+/// A typed value stored in one persistent memory slot.
+#[derive(Debug)]
+pub struct Field<T> {
+    pub slot: u8,
+    _value: PhantomData<T>,
+}
+
+impl<T> Clone for Field<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Field<T> {}
+
+impl<T> Field<T> {
+    /// Declare a field, slots below [`HEADER_SLOTS`] are rejected at compile
+    /// time when used in a constant.
+    pub const fn new(slot: u8) -> Self {
+        assert!(slot as usize >= HEADER_SLOTS, "slot is used by the header");
+        Self {
+            slot,
+            _value: PhantomData,
+        }
+    }
+}
+
+/// Upgrades the data slots from one schema version to the next.
+pub type Migration = fn(&mut [u32; SLOTS]);
+
+/// Identifies a game's save format.
+#[derive(Clone, Copy)]
+pub struct Schema {
+    pub magic: u32,
+    pub version: u32,
+    /// The oldest version that can still be loaded.
+    pub first_version: u32,
+    /// `migrations[n]` upgrades version `first_version + n` by one.
+    pub migrations: &'static [Migration],
+}
+
+/// What was found in persistent memory on [`PersistentStore::open`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LoadStatus {
+    Loaded,
+    /// An older save was upgraded.
+    Migrated {
+        from: u32,
+    },
+    /// Nothing saved yet, or a different game's data.
+    Empty,
+    /// The checksum didn't match, the data was reset.
+    Corrupted,
+    /// Saved by a newer version or with a missing migration, the data was reset.
+    Unsupported {
+        version: u32,
+    },
+}
+
+/// Typed fields over `pmem` with a header to detect foreign, corrupted or
+/// older saves. Values are cached, [`PersistentStore::save`] writes them back.
+pub struct PersistentStore {
+    schema: Schema,
+    slots: [u32; SLOTS],
+    dirty: bool,
+}
+
+/// Checksum of the data slots, FNV-1a over their bytes.
+pub fn checksum(slots: &[u32; SLOTS]) -> u32 {
+    let mut hash = 0x811c_9dc5_u32;
+    for value in &slots[HEADER_SLOTS..] {
+        for byte in value.to_le_bytes() {
+            hash ^= byte as u32;
+            hash = hash.wrapping_mul(0x0100_0193);
+        }
+    }
+    hash
+}
+
+impl PersistentStore {
+    /// Load and check persistent memory. Anything but a valid save of this
+    /// schema (after migrations) leaves the store zeroed.
+    pub fn open(schema: Schema) -> (Self, LoadStatus) {
+        let mut slots = [0; SLOTS];
+        for (index, slot) in slots.iter_mut().enumerate() {
+            *slot = pmem_get(index as i32);
+        }
+        Self::from_slots(schema, slots)
+    }
+
+    /// Check slots read from persistent memory, like [`PersistentStore::open`].
+    pub fn from_slots(schema: Schema, slots: [u32; SLOTS]) -> (Self, LoadStatus) {
+        let mut store = Self {
+            schema,
+            slots,
+            dirty: false,
+        };
+        let status = store.validate();
+        if !matches!(status, LoadStatus::Loaded) {
+            store.dirty = true;
+        }
+        if !matches!(status, LoadStatus::Loaded | LoadStatus::Migrated { .. }) {
+            store.clear();
+        }
+        (store, status)
+    }
+
+    fn validate(&mut self) -> LoadStatus {
+        if self.slots[MAGIC_SLOT] != self.schema.magic {
+            return LoadStatus::Empty;
+        }
+        if self.slots[CHECKSUM_SLOT] != checksum(&self.slots) {
+            return LoadStatus::Corrupted;
+        }
+        let from = self.slots[VERSION_SLOT];
+        if from > self.schema.version || from < self.schema.first_version {
+            return LoadStatus::Unsupported { version: from };
+        }
+        for version in from..self.schema.version {
+            let index = (version - self.schema.first_version) as usize;
+            match self.schema.migrations.get(index) {
+                Some(migrate) => migrate(&mut self.slots),
+                None => return LoadStatus::Unsupported { version: from },
+            }
+        }
+        if from < self.schema.version {
+            LoadStatus::Migrated { from }
+        } else {
+            LoadStatus::Loaded
+        }
+    }
+
+    pub fn get<T: SlotValue>(&self, field: Field<T>) -> T {
+        T::from_slot(self.slots[field.slot as usize])
+    }
+
+    pub fn set<T: SlotValue>(&mut self, field: Field<T>, value: T) {
+        let value = value.to_slot();
+        let slot = &mut self.slots[field.slot as usize];
+        if *slot != value {
+            *slot = value;
+            self.dirty = true;
+        }
+    }
+
+    /// Zero every data slot.
+    pub fn clear(&mut self) {
+        self.slots = [0; SLOTS];
+        self.dirty = true;
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Raw data slots, for code that packs its own data.
+    pub fn slots(&self) -> &[u32] {
+        &self.slots[HEADER_SLOTS..]
+    }
+
+    pub fn slots_mut(&mut self) -> &mut [u32] {
+        self.dirty = true;
+        &mut self.slots[HEADER_SLOTS..]
+    }
+
+    /// Write the data and a fresh header to `pmem` if anything changed.
+    pub fn save(&mut self) {
+        if !self.dirty {
+            return;
+        }
+        self.slots[MAGIC_SLOT] = self.schema.magic;
+        self.slots[VERSION_SLOT] = self.schema.version;
+        self.slots[CHECKSUM_SLOT] = checksum(&self.slots);
+        for (index, &value) in self.slots.iter().enumerate() {
+            pmem_set(index as i32, value);
+        }
+        self.dirty = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCORE: Field<u32> = Field::new(3);
+    const SPEED: Field<Fixed> = Field::new(4);
+    const MUTED: Field<bool> = Field::new(5);
+
+    fn double_score(slots: &mut [u32; SLOTS]) {
+        slots[3] *= 2;
+    }
+
+    const SCHEMA: Schema = Schema {
+        magic: 0x5a7e_0001,
+        version: 1,
+        first_version: 0,
+        migrations: &[double_score],
+    };
+
+    /// Saved slots, the way `save` leaves them.
+    fn saved(version: u32, score: u32) -> [u32; SLOTS] {
+        let mut slots = [0; SLOTS];
+        slots[MAGIC_SLOT] = SCHEMA.magic;
+        slots[VERSION_SLOT] = version;
+        slots[3] = score;
+        slots[CHECKSUM_SLOT] = checksum(&slots);
+        slots
+    }
+
+    #[test]
+    fn loads_a_valid_save() {
+        let (store, status) = PersistentStore::from_slots(SCHEMA, saved(1, 40));
+        assert_eq!(status, LoadStatus::Loaded);
+        assert_eq!(store.get(SCORE), 40);
+        assert!(!store.is_dirty());
+    }
+
+    #[test]
+    fn migrates_older_saves() {
+        let (store, status) = PersistentStore::from_slots(SCHEMA, saved(0, 40));
+        assert_eq!(status, LoadStatus::Migrated { from: 0 });
+        assert_eq!(store.get(SCORE), 80);
+        assert!(store.is_dirty());
+    }
+
+    #[test]
+    fn resets_foreign_corrupted_and_newer_data() {
+        let (store, status) = PersistentStore::from_slots(SCHEMA, [7; SLOTS]);
+        assert_eq!(status, LoadStatus::Empty);
+        assert_eq!(store.get(SCORE), 0);
+
+        let mut slots = saved(1, 40);
+        slots[10] = 1;
+        let (store, status) = PersistentStore::from_slots(SCHEMA, slots);
+        assert_eq!(status, LoadStatus::Corrupted);
+        assert_eq!(store.get(SCORE), 0);
+
+        let (_, status) = PersistentStore::from_slots(SCHEMA, saved(2, 40));
+        assert_eq!(status, LoadStatus::Unsupported { version: 2 });
+    }
+
+    #[test]
+    fn missing_migrations_are_unsupported() {
+        let schema = Schema {
+            migrations: &[],
+            ..SCHEMA
+        };
+        let (store, status) = PersistentStore::from_slots(schema, saved(0, 40));
+        assert_eq!(status, LoadStatus::Unsupported { version: 0 });
+        assert_eq!(store.get(SCORE), 0);
+    }
+
+    #[test]
+    fn migrations_start_at_the_first_version() {
+        fn add_one(slots: &mut [u32; SLOTS]) {
+            slots[3] += 1;
+        }
+        let schema = Schema {
+            version: 4,
+            first_version: 2,
+            migrations: &[double_score, add_one],
+            ..SCHEMA
+        };
+        let (store, status) = PersistentStore::from_slots(schema, saved(2, 40));
+        assert_eq!(status, LoadStatus::Migrated { from: 2 });
+        assert_eq!(store.get(SCORE), 81);
+        let (store, status) = PersistentStore::from_slots(schema, saved(3, 40));
+        assert_eq!(status, LoadStatus::Migrated { from: 3 });
+        assert_eq!(store.get(SCORE), 41);
+        let (_, status) = PersistentStore::from_slots(schema, saved(1, 40));
+        assert_eq!(status, LoadStatus::Unsupported { version: 1 });
+    }
+
+    #[test]
+    fn values_round_trip() {
+        let (mut store, _) = PersistentStore::from_slots(SCHEMA, saved(1, 0));
+        store.set(SPEED, Fixed::from_f32(-1.5));
+        store.set(MUTED, true);
+        assert_eq!(store.get(SPEED).to_f32(), -1.5);
+        assert!(store.get(MUTED));
+        assert!(store.is_dirty());
+    }
+
+    #[test]
+    fn unchanged_values_stay_clean() {
+        let (mut store, _) = PersistentStore::from_slots(SCHEMA, saved(1, 40));
+        store.set(SCORE, 40);
+        assert!(!store.is_dirty());
+    }
+}
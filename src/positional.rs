@@ -0,0 +1,162 @@
+use crate::audio::{sfx_channel_playing, AudioManager};
+use crate::tic80::*;
+
+const MAX_VOLUME: f32 = 15.0;
+
+/// How volume falls off with distance and how wide the stereo field is, in pixels.
+#[derive(Clone, Copy, Debug)]
+pub struct Attenuation {
+    /// Full volume up to this distance.
+    pub min_distance: f32,
+    /// Silent from this distance on.
+    pub max_distance: f32,
+    /// Horizontal offset at which a sound is fully on one side.
+    pub pan_width: f32,
+}
+
+impl Default for Attenuation {
+    fn default() -> Self {
+        Self {
+            min_distance: 16.0,
+            max_distance: 160.0,
+            pan_width: 120.0,
+        }
+    }
+}
+
+impl Attenuation {
+    /// Left and right volume 0.0..=1.0 of a sound at `x`, `y` heard from
+    /// `listener_x`, `listener_y`.
+    pub fn stereo(&self, listener_x: f32, listener_y: f32, x: f32, y: f32) -> (f32, f32) {
+        let dx = x - listener_x;
+        let dy = y - listener_y;
+        let distance = (dx * dx + dy * dy).sqrt();
+        let gain = if distance <= self.min_distance {
+            1.0
+        } else if distance >= self.max_distance {
+            0.0
+        } else {
+            1.0 - (distance - self.min_distance) / (self.max_distance - self.min_distance)
+        };
+        let pan = if self.pan_width > 0.0 {
+            (dx / self.pan_width).clamp(-1.0, 1.0)
+        } else {
+            0.0
+        };
+        // The near side stays at full level, the far side drops off.
+        (gain * (1.0 - pan).min(1.0), gain * (1.0 + pan).min(1.0))
+    }
+
+    /// Pan a one-shot effect at `position` through the `sfx` volumes, then
+    /// play it with [`AudioManager::play`]. Returns `false` when the sound
+    /// is out of earshot and needn't be played.
+    pub fn pan(&self, listener: (f32, f32), position: (f32, f32), sfx: &mut Sfx) -> bool {
+        let (left, right) = self.stereo(listener.0, listener.1, position.0, position.1);
+        sfx.volume_left((left * MAX_VOLUME + 0.5) as i32)
+            .volume_right((right * MAX_VOLUME + 0.5) as i32);
+        left > 0.0 || right > 0.0
+    }
+}
+
+/// A long running sound at a world position, like a fountain or a boss,
+/// re-panned every frame through the channel's stereo volume.
+#[derive(Clone, Debug)]
+pub struct SoundSource {
+    pub id: i32,
+    pub x: f32,
+    pub y: f32,
+    pub priority: u8,
+    pub attenuation: Attenuation,
+    channel: Option<usize>,
+    /// Started since the last update, `SFX_STATE` doesn't show it yet.
+    starting: bool,
+}
+
+impl SoundSource {
+    pub fn new(id: i32, x: f32, y: f32) -> Self {
+        Self {
+            id,
+            x,
+            y,
+            priority: 0,
+            attenuation: Attenuation::default(),
+            channel: None,
+            starting: false,
+        }
+    }
+
+    /// Start playing for `duration` frames, -1 plays until stopped.
+    pub fn play(&mut self, audio: &mut AudioManager, duration: i32) -> Option<usize> {
+        self.channel = audio.play(self.id, self.priority, duration, Sfx::default());
+        self.starting = self.channel.is_some();
+        self.channel
+    }
+
+    pub fn stop(&mut self, audio: &mut AudioManager) {
+        if let Some(channel) = self.channel.take() {
+            audio.stop(channel);
+            audio.set_channel_stereo(channel, 1.0, 1.0);
+        }
+    }
+
+    pub fn channel(&self) -> Option<usize> {
+        self.channel
+    }
+
+    /// Update the panning from the listener position while the effect is
+    /// playing. Call once per frame before [`AudioManager::update`].
+    pub fn update(&mut self, audio: &mut AudioManager, listener_x: f32, listener_y: f32) {
+        let Some(channel) = self.channel else {
+            return;
+        };
+        // TIC-80 only reports the effect from the tick after it started.
+        let starting = std::mem::take(&mut self.starting);
+        let stopped = !starting && !sfx_channel_playing(channel);
+        if stopped || audio.playing(channel) != Some(self.id) {
+            self.channel = None;
+            audio.set_channel_stereo(channel, 1.0, 1.0);
+            return;
+        }
+        let (left, right) = self
+            .attenuation
+            .stereo(listener_x, listener_y, self.x, self.y);
+        audio.set_channel_stereo(channel, left, right);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: (f32, f32), b: (f32, f32)) -> bool {
+        (a.0 - b.0).abs() < 0.01 && (a.1 - b.1).abs() < 0.01
+    }
+
+    #[test]
+    fn volume_falls_off_with_distance() {
+        let attenuation = Attenuation::default();
+        assert!(close(attenuation.stereo(0.0, 0.0, 0.0, 10.0), (1.0, 1.0)));
+        assert!(close(attenuation.stereo(0.0, 0.0, 0.0, 88.0), (0.5, 0.5)));
+        assert!(close(attenuation.stereo(0.0, 0.0, 0.0, 200.0), (0.0, 0.0)));
+    }
+
+    #[test]
+    fn near_side_stays_loud() {
+        let attenuation = Attenuation::default();
+        let (left, right) = attenuation.stereo(0.0, 0.0, 12.0, 0.0);
+        assert!(close((left, right), (0.9, 1.0)));
+        let (left, right) = attenuation.stereo(0.0, 0.0, -300.0, 0.0);
+        assert_eq!((left, right), (0.0, 0.0));
+    }
+
+    #[test]
+    fn pans_fully_at_the_pan_width() {
+        let attenuation = Attenuation {
+            max_distance: 1000.0,
+            ..Default::default()
+        };
+        let (left, right) = attenuation.stereo(0.0, 0.0, 120.0, 0.0);
+        assert!(left.abs() < 0.01);
+        assert!(right > 0.8);
+    }
+}
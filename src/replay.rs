@@ -0,0 +1,298 @@
+use crate::tic80::*;
+use crate::tic80_error::Tic80Error;
+
+/// Bytes of input state per frame: `GAMEPADS`, `MOUSE` then `KEYBOARD`.
+pub const FRAME_BYTES: usize = 12;
+const RUN_BYTES: usize = 2 + FRAME_BYTES;
+
+/// The input regions of RAM for one frame.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct InputFrame(pub [u8; FRAME_BYTES]);
+
+impl InputFrame {
+    /// The input TIC-80 wrote for this frame.
+    pub fn read() -> Self {
+        let mut bytes = [0; FRAME_BYTES];
+        unsafe {
+            bytes[0..4].copy_from_slice(&*GAMEPADS);
+            bytes[4..8].copy_from_slice(&*MOUSE);
+            bytes[8..12].copy_from_slice(&*KEYBOARD);
+        }
+        Self(bytes)
+    }
+
+    /// Replace this frame's input. `btn`, `key` and `mouse` read these
+    /// regions, so they see the written state. `btnp` and `keyp` do too
+    /// while [`InputReplay`] counts their holds.
+    pub fn write(&self) {
+        unsafe {
+            (&mut *GAMEPADS).copy_from_slice(&self.0[0..4]);
+            (&mut *MOUSE).copy_from_slice(&self.0[4..8]);
+            (&mut *KEYBOARD).copy_from_slice(&self.0[8..12]);
+        }
+    }
+
+    pub fn gamepads(&self) -> [u8; 4] {
+        [self.0[0], self.0[1], self.0[2], self.0[3]]
+    }
+
+    pub fn keyboard(&self) -> [u8; 4] {
+        [self.0[8], self.0[9], self.0[10], self.0[11]]
+    }
+}
+
+/// A run of identical frames.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct Run {
+    frames: u16,
+    input: InputFrame,
+}
+
+/// Recorded input, run-length encoded, with the RNG seed the game used.
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct Recording {
+    pub seed: u32,
+    runs: Vec<Run>,
+}
+
+impl Recording {
+    pub fn new(seed: u32) -> Self {
+        Self {
+            seed,
+            runs: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, input: InputFrame) {
+        match self.runs.last_mut() {
+            Some(run) if run.input == input && run.frames < u16::MAX => run.frames += 1,
+            _ => self.runs.push(Run { frames: 1, input }),
+        }
+    }
+
+    pub fn frames(&self) -> usize {
+        self.runs.iter().map(|run| run.frames as usize).sum()
+    }
+
+    /// The input of frame `index`.
+    pub fn frame(&self, index: usize) -> Option<InputFrame> {
+        let mut start = 0;
+        for run in &self.runs {
+            start += run.frames as usize;
+            if index < start {
+                return Some(run.input);
+            }
+        }
+        None
+    }
+
+    /// The seed, then each run as a little endian frame count and the
+    /// frame's bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(4 + self.runs.len() * RUN_BYTES);
+        bytes.extend_from_slice(&self.seed.to_le_bytes());
+        for run in &self.runs {
+            bytes.extend_from_slice(&run.frames.to_le_bytes());
+            bytes.extend_from_slice(&run.input.0);
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Tic80Error> {
+        if bytes.len() < 4 || !(bytes.len() - 4).is_multiple_of(RUN_BYTES) {
+            return Err(Tic80Error::InvalidData("recording length"));
+        }
+        let seed = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        let runs = bytes[4..]
+            .chunks(RUN_BYTES)
+            .map(|chunk| {
+                let mut input = [0; FRAME_BYTES];
+                input.copy_from_slice(&chunk[2..]);
+                Run {
+                    frames: u16::from_le_bytes([chunk[0], chunk[1]]),
+                    input: InputFrame(input),
+                }
+            })
+            .filter(|run| run.frames > 0)
+            .collect();
+        Ok(Self { seed, runs })
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+enum State {
+    #[default]
+    Idle,
+    Recording(Recording),
+    Replaying {
+        recording: Recording,
+        run: usize,
+        frame: u16,
+    },
+}
+
+/// Records the input of every frame, or plays a [`Recording`] back by
+/// writing it over the input regions. Call [`InputReplay::update`] first
+/// thing each frame, before anything reads input. While replaying, `btnp`
+/// and `keyp` count their repeats from the written input.
+#[derive(Clone, Debug, Default)]
+pub struct InputReplay {
+    state: State,
+}
+
+impl InputReplay {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start recording from this frame. Seed the game's RNG with `seed` so
+    /// the replay makes the same rolls.
+    pub fn record(&mut self, seed: u32) {
+        count_holds(false);
+        self.state = State::Recording(Recording::new(seed));
+    }
+
+    /// Play `recording` from this frame. Returns its seed for the game's RNG.
+    pub fn replay(&mut self, recording: Recording) -> u32 {
+        let seed = recording.seed;
+        count_holds(true);
+        self.state = State::Replaying {
+            recording,
+            run: 0,
+            frame: 0,
+        };
+        seed
+    }
+
+    /// Stop recording or replaying. Returns what was recorded.
+    pub fn stop(&mut self) -> Option<Recording> {
+        count_holds(false);
+        match std::mem::take(&mut self.state) {
+            State::Recording(recording) => Some(recording),
+            _ => None,
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        matches!(self.state, State::Recording(_))
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        matches!(self.state, State::Replaying { .. })
+    }
+
+    pub fn update(&mut self) {
+        match &mut self.state {
+            State::Idle => {}
+            State::Recording(recording) => recording.push(InputFrame::read()),
+            State::Replaying {
+                recording,
+                run,
+                frame,
+            } => match recording.runs.get(*run) {
+                Some(current) => {
+                    current.input.write();
+                    advance_hold_counts(current.input.gamepads(), current.input.keyboard());
+                    *frame += 1;
+                    if *frame >= current.frames {
+                        *run += 1;
+                        *frame = 0;
+                    }
+                }
+                None => {
+                    count_holds(false);
+                    self.state = State::Idle;
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(byte: u8) -> InputFrame {
+        InputFrame([byte; FRAME_BYTES])
+    }
+
+    /// The frames `btnp(id, hold, period)` reads as pressed while `id` is
+    /// held for `frames`.
+    fn button_presses(id: i32, hold: i32, period: i32, frames: u32) -> Vec<u32> {
+        let mut holds = HoldCounts::new();
+        let mut presses = Vec::new();
+        for frame in 1..=frames {
+            holds.advance((1_u32 << id).to_le_bytes(), [0; 4]);
+            if holds.btnp(id, hold, period) {
+                presses.push(frame);
+            }
+        }
+        presses
+    }
+
+    #[test]
+    fn counts_button_repeats_like_tic80() {
+        assert_eq!(button_presses(4, -1, -1, 40), [1]);
+        assert_eq!(button_presses(4, 20, 5, 40), [1, 20, 25, 30, 35, 40]);
+        assert_eq!(button_presses(4, 3, 0, 5), [1, 3, 4, 5]);
+    }
+
+    #[test]
+    fn releasing_resets_the_counts() {
+        let mut holds = HoldCounts::new();
+        holds.advance([0b10, 0, 0, 0], [KEY_A as u8, 0, 0, 0]);
+        assert!(holds.btnp(1, -1, -1) && holds.keyp(KEY_A, -1, -1));
+        assert_eq!(holds.btnp_bits(), 0b10);
+        holds.advance([0b10, 0, 0, 0], [0; 4]);
+        assert!(!holds.btnp(1, -1, -1) && !holds.keyp(KEY_A, -1, -1));
+        assert!(!holds.keyp(-1, -1, -1));
+        holds.advance([0; 4], [0, KEY_A as u8, 0, 0]);
+        holds.advance([0b10, 0, 0, 0], [0, KEY_A as u8, 0, 0]);
+        assert!(holds.btnp(1, -1, -1) && !holds.keyp(KEY_A, -1, -1));
+        assert!(holds.btnp(-1, -1, -1));
+    }
+
+    #[test]
+    fn identical_frames_share_a_run() {
+        let mut recording = Recording::new(9);
+        for byte in [1, 1, 1, 2, 1] {
+            recording.push(input(byte));
+        }
+        assert_eq!(recording.runs.len(), 3);
+        assert_eq!(recording.frames(), 5);
+        assert_eq!(recording.frame(2), Some(input(1)));
+        assert_eq!(recording.frame(3), Some(input(2)));
+        assert_eq!(recording.frame(4), Some(input(1)));
+        assert_eq!(recording.frame(5), None);
+    }
+
+    #[test]
+    fn long_runs_split() {
+        let mut recording = Recording::new(0);
+        for _ in 0..u16::MAX as usize + 2 {
+            recording.push(input(0));
+        }
+        assert_eq!(recording.runs.len(), 2);
+        assert_eq!(recording.frames(), u16::MAX as usize + 2);
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let mut recording = Recording::new(0x0102_0304);
+        recording.push(input(7));
+        recording.push(input(7));
+        recording.push(input(8));
+        let bytes = recording.to_bytes();
+        assert_eq!(bytes.len(), 4 + 2 * RUN_BYTES);
+        assert_eq!(&bytes[..6], &[4, 3, 2, 1, 2, 0]);
+        assert_eq!(Recording::from_bytes(&bytes).unwrap(), recording);
+    }
+
+    #[test]
+    fn rejects_bad_lengths_and_drops_empty_runs() {
+        assert!(Recording::from_bytes(&[0; 3]).is_err());
+        assert!(Recording::from_bytes(&[0; 4 + RUN_BYTES - 1]).is_err());
+        let recording = Recording::from_bytes(&[0; 4 + RUN_BYTES]).unwrap();
+        assert_eq!(recording.frames(), 0);
+    }
+}
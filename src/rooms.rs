@@ -0,0 +1,386 @@
+use crate::camera::{Bounds, Camera};
+use crate::map_layer::TILE_SIZE;
+use crate::tic80::*;
+use crate::tic80_error::Tic80Error;
+
+/// A rectangle of map tiles the camera stays inside.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Room {
+    pub x: i32,
+    pub y: i32,
+    pub w: i32,
+    pub h: i32,
+}
+
+impl Room {
+    pub const fn new(x: i32, y: i32, w: i32, h: i32) -> Self {
+        Self { x, y, w, h }
+    }
+
+    pub fn contains_tile(&self, tx: i32, ty: i32) -> bool {
+        tx >= self.x && ty >= self.y && tx < self.x + self.w && ty < self.y + self.h
+    }
+
+    pub fn overlaps(&self, other: &Room) -> bool {
+        self.x < other.x + other.w
+            && other.x < self.x + self.w
+            && self.y < other.y + other.h
+            && other.y < self.y + self.h
+    }
+
+    pub fn bounds(&self) -> Bounds {
+        Bounds::tiles(self.x, self.y, self.w, self.h)
+    }
+}
+
+/// The rooms of a floor.
+#[derive(Clone, Debug, Default)]
+pub struct Rooms {
+    rooms: Vec<Room>,
+}
+
+impl Rooms {
+    pub fn from_table(rooms: &[Room]) -> Self {
+        Self {
+            rooms: rooms.to_vec(),
+        }
+    }
+
+    /// Rooms marked in the `w` x `h` tiles of MAP at `(x, y)`, see
+    /// [`Rooms::from_corners`]. A `marker` tile is a room's top-left
+    /// corner. Markers are replaced with `floor` in MAP so they aren't
+    /// drawn, the bank still has them for the next scan after a reload.
+    pub fn from_markers(
+        x: i32,
+        y: i32,
+        w: i32,
+        h: i32,
+        marker: i32,
+        floor: i32,
+    ) -> Result<Self, Tic80Error> {
+        let mut corners = Vec::new();
+        for ty in 0..h {
+            for tx in 0..w {
+                if mget(x + tx, y + ty) == marker {
+                    corners.push((x + tx, y + ty));
+                    mset(x + tx, y + ty, floor);
+                }
+            }
+        }
+        Self::from_corners(&corners, x, y, w, h)
+    }
+
+    /// Rooms in the `w` x `h` tiles at `(x, y)` from their top-left
+    /// corners, all in map tiles. A room reaches right to the next corner
+    /// on its row and down to the next corner below it, or to the region's
+    /// edge. The region's top-left corner always starts a room, so no
+    /// corners is a single room.
+    ///
+    /// Layouts whose rooms would overlap, where the rooms of one row don't
+    /// line up with those below, are an error.
+    pub fn from_corners(
+        corners: &[(i32, i32)],
+        x: i32,
+        y: i32,
+        w: i32,
+        h: i32,
+    ) -> Result<Self, Tic80Error> {
+        let region = Room::new(x, y, w, h);
+        let mut corners: Vec<(i32, i32)> = corners
+            .iter()
+            .copied()
+            .filter(|&(cx, cy)| region.contains_tile(cx, cy))
+            .collect();
+        if !corners.contains(&(x, y)) {
+            corners.insert(0, (x, y));
+        }
+        let rooms: Vec<Room> = corners
+            .iter()
+            .map(|&(cx, cy)| {
+                let right = corners
+                    .iter()
+                    .filter(|&&(ox, oy)| oy == cy && ox > cx)
+                    .map(|&(ox, _)| ox)
+                    .min()
+                    .unwrap_or(x + w);
+                let bottom = corners
+                    .iter()
+                    .filter(|&&(ox, oy)| ox >= cx && ox < right && oy > cy)
+                    .map(|&(_, oy)| oy)
+                    .min()
+                    .unwrap_or(y + h);
+                Room::new(cx, cy, right - cx, bottom - cy)
+            })
+            .collect();
+        for (i, room) in rooms.iter().enumerate() {
+            if rooms[i + 1..].iter().any(|other| room.overlaps(other)) {
+                return Err(Tic80Error::InvalidData("room markers overlap"));
+            }
+        }
+        Ok(Self { rooms })
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Room> {
+        self.rooms.get(index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.rooms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rooms.is_empty()
+    }
+
+    /// The room containing world pixel `(x, y)`, the first one listed when
+    /// rooms from a table overlap.
+    pub fn room_at(&self, x: i32, y: i32) -> Option<usize> {
+        let (tx, ty) = (x.div_euclid(TILE_SIZE), y.div_euclid(TILE_SIZE));
+        self.rooms
+            .iter()
+            .position(|room| room.contains_tile(tx, ty))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TransitionStyle {
+    /// Slide the view over to the next room.
+    Scroll,
+    /// Fade to black, move, and fade back in.
+    Fade,
+}
+
+#[derive(Clone, Debug)]
+struct Transition {
+    to: usize,
+    frame: u32,
+    from: (f32, f32),
+    target: (f32, f32),
+    /// The palette before fading, restored afterwards.
+    palette: [u8; 48],
+}
+
+/// Locks the camera to the room the player is in and moves it to the next
+/// room when the player crosses an edge.
+///
+/// Call [`RoomTransitions::update`] once a frame with the point the camera
+/// follows. While it returns `true` gameplay should be frozen and the
+/// camera left alone.
+#[derive(Clone, Debug)]
+pub struct RoomTransitions {
+    rooms: Rooms,
+    current: Option<usize>,
+    style: TransitionStyle,
+    frames: u32,
+    transition: Option<Transition>,
+}
+
+impl RoomTransitions {
+    pub fn new(rooms: Rooms) -> Self {
+        Self {
+            rooms,
+            current: None,
+            style: TransitionStyle::Scroll,
+            frames: 32,
+            transition: None,
+        }
+    }
+
+    pub fn style(mut self, style: TransitionStyle) -> Self {
+        self.style = style;
+        self
+    }
+
+    /// Length of a transition, 32 frames by default.
+    pub fn frames(mut self, frames: u32) -> Self {
+        self.frames = frames.max(1);
+        self
+    }
+
+    pub fn rooms(&self) -> &Rooms {
+        &self.rooms
+    }
+
+    /// Replace the rooms, e.g. on a new floor. Call [`RoomTransitions::enter`]
+    /// afterwards.
+    pub fn set_rooms(&mut self, rooms: Rooms) {
+        self.finish();
+        self.rooms = rooms;
+        self.current = None;
+    }
+
+    pub fn current(&self) -> Option<usize> {
+        self.current
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.transition.is_some()
+    }
+
+    /// Switch to the room at `(x, y)` without a transition, e.g. after
+    /// loading a game. Snaps the camera.
+    pub fn enter(&mut self, camera: &mut Camera, x: i32, y: i32) {
+        self.finish();
+        self.current = self.rooms.room_at(x, y);
+        camera.set_bounds(self.current.map(|i| self.rooms.rooms[i].bounds()));
+        camera.snap();
+    }
+
+    /// Start or advance a transition for the followed point `(x, y)`.
+    /// Returns `true` while one is running.
+    pub fn update(&mut self, camera: &mut Camera, x: i32, y: i32) -> bool {
+        if self.transition.is_none() {
+            match self.rooms.room_at(x, y) {
+                Some(room) if Some(room) != self.current => self.start(camera, room),
+                _ => return false,
+            }
+        }
+        let Some(transition) = &mut self.transition else {
+            return false;
+        };
+        transition.frame += 1;
+        let t = transition.frame as f32 / self.frames as f32;
+        match self.style {
+            TransitionStyle::Scroll => {
+                // Ease in and out.
+                let s = t * t * (3.0 - 2.0 * t);
+                camera.x = transition.from.0 + (transition.target.0 - transition.from.0) * s;
+                camera.y = transition.from.1 + (transition.target.1 - transition.from.1) * s;
+            }
+            TransitionStyle::Fade => {
+                if t >= 0.5 {
+                    (camera.x, camera.y) = transition.target;
+                }
+                let brightness = (2.0 * t - 1.0).abs();
+                let palette = unsafe { &mut *PALETTE };
+                for (color, &original) in palette.iter_mut().zip(&transition.palette) {
+                    *color = (original as f32 * brightness) as u8;
+                }
+            }
+        }
+        if transition.frame >= self.frames {
+            let to = transition.to;
+            self.finish();
+            self.current = Some(to);
+            camera.set_bounds(Some(self.rooms.rooms[to].bounds()));
+        }
+        true
+    }
+
+    fn start(&mut self, camera: &mut Camera, room: usize) {
+        // Where the camera settles in the new room.
+        let mut settled = camera.clone();
+        settled.set_bounds(Some(self.rooms.rooms[room].bounds()));
+        settled.snap();
+        self.transition = Some(Transition {
+            to: room,
+            frame: 0,
+            from: (camera.x, camera.y),
+            target: (settled.x, settled.y),
+            palette: unsafe { *PALETTE },
+        });
+        camera.set_bounds(None);
+    }
+
+    /// End a running transition, restoring a faded palette.
+    fn finish(&mut self) {
+        if let Some(transition) = self.transition.take() {
+            if self.style == TransitionStyle::Fade {
+                unsafe { (&mut *PALETTE).copy_from_slice(&transition.palette) }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rooms(corners: &[(i32, i32)], w: i32, h: i32) -> Vec<Room> {
+        let rooms = Rooms::from_corners(corners, 0, 0, w, h).unwrap();
+        (0..rooms.len()).map(|i| *rooms.get(i).unwrap()).collect()
+    }
+
+    #[test]
+    fn no_corners_is_one_room() {
+        assert_eq!(rooms(&[], 30, 17), [Room::new(0, 0, 30, 17)]);
+    }
+
+    #[test]
+    fn splits_a_grid() {
+        let corners = [(0, 0), (30, 0), (0, 17), (30, 17)];
+        assert_eq!(
+            rooms(&corners, 60, 34),
+            [
+                Room::new(0, 0, 30, 17),
+                Room::new(30, 0, 30, 17),
+                Room::new(0, 17, 30, 17),
+                Room::new(30, 17, 30, 17),
+            ]
+        );
+    }
+
+    #[test]
+    fn lower_rows_can_split_further() {
+        let corners = [(0, 0), (0, 17), (20, 17), (40, 17)];
+        assert_eq!(
+            rooms(&corners, 60, 34),
+            [
+                Room::new(0, 0, 60, 17),
+                Room::new(0, 17, 20, 17),
+                Room::new(20, 17, 20, 17),
+                Room::new(40, 17, 20, 17),
+            ]
+        );
+    }
+
+    #[test]
+    fn the_region_corner_is_implied() {
+        assert_eq!(
+            rooms(&[(10, 0)], 20, 5),
+            [Room::new(0, 0, 10, 5), Room::new(10, 0, 10, 5)]
+        );
+        // Corners outside the region are ignored.
+        assert_eq!(rooms(&[(20, 0), (-1, 2)], 20, 5), [Room::new(0, 0, 20, 5)]);
+    }
+
+    #[test]
+    fn rooms_start_at_the_region_origin() {
+        // The second floor of the map, below the first.
+        let rooms = Rooms::from_corners(&[(30, 17), (0, 0)], 0, 17, 60, 17).unwrap();
+        assert_eq!(rooms.get(0), Some(&Room::new(0, 17, 30, 17)));
+        assert_eq!(rooms.get(1), Some(&Room::new(30, 17, 30, 17)));
+        assert_eq!(rooms.len(), 2);
+        assert_eq!(rooms.room_at(5, 5), None);
+        assert_eq!(rooms.room_at(5, 17 * TILE_SIZE), Some(0));
+        assert_eq!(rooms.room_at(30 * TILE_SIZE, 20 * TILE_SIZE), Some(1));
+    }
+
+    #[test]
+    fn overlapping_layouts_are_errors() {
+        // The right room reaches down past where the lower left one starts.
+        let corners = [(0, 0), (10, 0), (0, 8), (5, 8)];
+        assert!(matches!(
+            Rooms::from_corners(&corners, 0, 0, 20, 16),
+            Err(Tic80Error::InvalidData(_))
+        ));
+    }
+
+    #[test]
+    fn finds_the_room_at_a_pixel() {
+        let rooms = Rooms::from_corners(&[(30, 0)], 0, 0, 60, 17).unwrap();
+        assert_eq!(rooms.room_at(0, 0), Some(0));
+        assert_eq!(rooms.room_at(30 * TILE_SIZE - 1, 5), Some(0));
+        assert_eq!(rooms.room_at(30 * TILE_SIZE, 5), Some(1));
+        assert_eq!(rooms.room_at(-1, 5), None);
+        assert_eq!(rooms.room_at(5, 17 * TILE_SIZE), None);
+    }
+
+    #[test]
+    fn overlap_needs_shared_tiles() {
+        let room = Room::new(0, 0, 10, 10);
+        assert!(room.overlaps(&Room::new(9, 9, 5, 5)));
+        assert!(!room.overlaps(&Room::new(10, 0, 5, 5)));
+        assert!(!room.overlaps(&Room::new(0, 10, 5, 5)));
+    }
+}
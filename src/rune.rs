@@ -0,0 +1,398 @@
+use std::f32::consts::PI;
+
+use crate::mouse::{Mouse, MouseButton};
+use crate::tic80::*;
+
+/// Points every stroke is resampled to before matching.
+pub const RESAMPLE_POINTS: usize = 32;
+/// Side of the square strokes are scaled to.
+const SQUARE_SIZE: f32 = 1.0;
+/// Strokes thinner than this ratio are scaled uniformly, so lines keep
+/// their shape.
+const ONE_DIMENSIONAL: f32 = 0.3;
+const ANGLE_RANGE: f32 = PI / 4.0;
+const ANGLE_PRECISION: f32 = PI / 90.0;
+const MAX_STROKE_POINTS: usize = 256;
+/// Pixels the pointer moves before another point is captured.
+const MIN_POINT_DISTANCE: f32 = 2.0;
+
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct Point {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl Point {
+    pub fn new(x: f32, y: f32) -> Self {
+        Self { x, y }
+    }
+
+    fn distance(self, other: Point) -> f32 {
+        ((other.x - self.x).powi(2) + (other.y - self.y).powi(2)).sqrt()
+    }
+}
+
+fn path_length(points: &[Point]) -> f32 {
+    points.windows(2).map(|w| w[0].distance(w[1])).sum()
+}
+
+fn centroid(points: &[Point]) -> Point {
+    let n = points.len().max(1) as f32;
+    let (x, y) = points
+        .iter()
+        .fold((0.0, 0.0), |(x, y), p| (x + p.x, y + p.y));
+    Point::new(x / n, y / n)
+}
+
+/// Evenly spaced points along the path, `None` for less than two points.
+fn resample(points: &[Point], n: usize) -> Option<Vec<Point>> {
+    if points.len() < 2 || n < 2 {
+        return None;
+    }
+    let interval = path_length(points) / (n - 1) as f32;
+    let mut result = vec![points[0]];
+    let mut walked = 0.0;
+    let mut previous = points[0];
+    for &point in &points[1..] {
+        let mut from = previous;
+        let mut d = from.distance(point);
+        while interval > 0.0 && walked + d >= interval && result.len() < n {
+            let t = (interval - walked) / d;
+            let q = Point::new(
+                from.x + t * (point.x - from.x),
+                from.y + t * (point.y - from.y),
+            );
+            result.push(q);
+            from = q;
+            d = from.distance(point);
+            walked = 0.0;
+        }
+        walked += d;
+        previous = point;
+    }
+    // Rounding can leave the last point out.
+    while result.len() < n {
+        result.push(points[points.len() - 1]);
+    }
+    Some(result)
+}
+
+fn rotate(points: &[Point], angle: f32) -> Vec<Point> {
+    let c = centroid(points);
+    let (sin, cos) = angle.sin_cos();
+    points
+        .iter()
+        .map(|p| {
+            let (dx, dy) = (p.x - c.x, p.y - c.y);
+            Point::new(dx * cos - dy * sin + c.x, dx * sin + dy * cos + c.y)
+        })
+        .collect()
+}
+
+fn scale_and_center(points: &[Point]) -> Vec<Point> {
+    let (min_x, max_x, min_y, max_y) = points.iter().fold(
+        (f32::MAX, f32::MIN, f32::MAX, f32::MIN),
+        |(min_x, max_x, min_y, max_y), p| {
+            (
+                min_x.min(p.x),
+                max_x.max(p.x),
+                min_y.min(p.y),
+                max_y.max(p.y),
+            )
+        },
+    );
+    let w = (max_x - min_x).max(f32::EPSILON);
+    let h = (max_y - min_y).max(f32::EPSILON);
+    let (sx, sy) = if w.min(h) / w.max(h) < ONE_DIMENSIONAL {
+        let s = SQUARE_SIZE / w.max(h);
+        (s, s)
+    } else {
+        (SQUARE_SIZE / w, SQUARE_SIZE / h)
+    };
+    let scaled: Vec<Point> = points
+        .iter()
+        .map(|p| Point::new(p.x * sx, p.y * sy))
+        .collect();
+    let c = centroid(&scaled);
+    scaled
+        .iter()
+        .map(|p| Point::new(p.x - c.x, p.y - c.y))
+        .collect()
+}
+
+/// Resample, rotate to the indicative angle (centroid to first point),
+/// scale to a square and move the centroid to the origin. `None` for paths
+/// that don't go anywhere.
+fn normalize(points: &[Point]) -> Option<Vec<Point>> {
+    if path_length(points) <= 0.0 {
+        return None;
+    }
+    let points = resample(points, RESAMPLE_POINTS)?;
+    let c = centroid(&points);
+    let angle = (c.y - points[0].y).atan2(c.x - points[0].x);
+    Some(scale_and_center(&rotate(&points, -angle)))
+}
+
+fn path_distance(a: &[Point], b: &[Point]) -> f32 {
+    a.iter().zip(b).map(|(p, q)| p.distance(*q)).sum::<f32>() / a.len() as f32
+}
+
+/// Smallest distance over rotations within [`ANGLE_RANGE`], by golden
+/// section search.
+fn distance_at_best_angle(points: &[Point], template: &[Point]) -> f32 {
+    let phi = 0.5 * (5.0_f32.sqrt() - 1.0);
+    let at = |angle: f32| path_distance(&rotate(points, angle), template);
+    let (mut a, mut b) = (-ANGLE_RANGE, ANGLE_RANGE);
+    let mut x1 = phi * a + (1.0 - phi) * b;
+    let mut f1 = at(x1);
+    let mut x2 = (1.0 - phi) * a + phi * b;
+    let mut f2 = at(x2);
+    while (b - a).abs() > ANGLE_PRECISION {
+        if f1 < f2 {
+            b = x2;
+            x2 = x1;
+            f2 = f1;
+            x1 = phi * a + (1.0 - phi) * b;
+            f1 = at(x1);
+        } else {
+            a = x1;
+            x1 = x2;
+            f1 = f2;
+            x2 = (1.0 - phi) * a + phi * b;
+            f2 = at(x2);
+        }
+    }
+    f1.min(f2)
+}
+
+/// A rune shape that produces `output` when recognised.
+#[derive(Clone, Debug)]
+pub struct Template<T> {
+    pub output: T,
+    points: Vec<Point>,
+}
+
+impl<T> Template<T> {
+    /// A template from a drawn path, `None` unless it has at least two
+    /// distinct points.
+    pub fn new(output: T, points: &[(f32, f32)]) -> Option<Self> {
+        let points: Vec<Point> = points.iter().map(|&(x, y)| Point::new(x, y)).collect();
+        Self::from_stroke(output, &points)
+    }
+
+    pub fn from_stroke(output: T, stroke: &[Point]) -> Option<Self> {
+        Some(Self {
+            output,
+            points: normalize(stroke)?,
+        })
+    }
+
+    /// Rust source for [`Template::new`] with this template's points, to
+    /// paste recorded runes into code.
+    pub fn to_source(&self, output: &str) -> String {
+        let points: Vec<String> = self
+            .points
+            .iter()
+            .map(|p| format!("({:.2}, {:.2})", p.x, p.y))
+            .collect();
+        format!("Template::new({}, &[{}])", output, points.join(", "))
+    }
+}
+
+/// The best template for a stroke.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct RuneMatch<T> {
+    pub output: T,
+    /// 0.0..=1.0, 1.0 is a perfect match.
+    pub score: f32,
+}
+
+/// Matches strokes against [`Template`]s in the way of the $1 unistroke
+/// recogniser.
+#[derive(Clone, Debug)]
+pub struct RuneRecognizer<T> {
+    templates: Vec<Template<T>>,
+    min_score: f32,
+}
+
+impl<T: Copy> RuneRecognizer<T> {
+    pub fn new() -> Self {
+        Self {
+            templates: Vec::new(),
+            min_score: 0.8,
+        }
+    }
+
+    pub fn with_template(mut self, template: Template<T>) -> Self {
+        self.templates.push(template);
+        self
+    }
+
+    /// Matches scoring under `score` are ignored, 0.8 by default.
+    pub fn min_score(mut self, score: f32) -> Self {
+        self.min_score = score;
+        self
+    }
+
+    pub fn add_template(&mut self, template: Template<T>) {
+        self.templates.push(template);
+    }
+
+    /// The best template for `stroke` and how well it matched, `None` when
+    /// nothing scores at least the minimum.
+    pub fn recognize(&self, stroke: &[Point]) -> Option<RuneMatch<T>> {
+        let points = normalize(stroke)?;
+        let half_diagonal = 0.5 * (2.0 * SQUARE_SIZE * SQUARE_SIZE).sqrt();
+        self.templates
+            .iter()
+            .map(|template| RuneMatch {
+                output: template.output,
+                score: 1.0 - distance_at_best_angle(&points, &template.points) / half_diagonal,
+            })
+            .filter(|m| m.score >= self.min_score)
+            .max_by(|a, b| a.score.total_cmp(&b.score))
+    }
+}
+
+impl<T: Copy> Default for RuneRecognizer<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Collects the pointer path while the left mouse button is held.
+#[derive(Clone, Debug, Default)]
+pub struct StrokeCapture {
+    points: Vec<Point>,
+}
+
+impl StrokeCapture {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Follow the mouse, call after [`Mouse::update`]. Returns the stroke
+    /// when the button is released.
+    pub fn update(&mut self, mouse: &Mouse) -> Option<Vec<Point>> {
+        let point = Point::new(mouse.x as f32, mouse.y as f32);
+        if mouse.just_pressed(MouseButton::Left) {
+            self.points.clear();
+            self.points.push(point);
+        } else if mouse.down(MouseButton::Left) {
+            let far_enough = self
+                .points
+                .last()
+                .is_none_or(|last| last.distance(point) >= MIN_POINT_DISTANCE);
+            if far_enough && self.points.len() < MAX_STROKE_POINTS {
+                self.points.push(point);
+            }
+        } else if mouse.just_released(MouseButton::Left) {
+            let stroke = std::mem::take(&mut self.points);
+            if stroke.len() >= 2 {
+                return Some(stroke);
+            }
+        }
+        None
+    }
+
+    pub fn is_drawing(&self) -> bool {
+        !self.points.is_empty()
+    }
+
+    /// Draw the stroke so far.
+    pub fn draw(&self, color: i8) {
+        for w in self.points.windows(2) {
+            line(w[0].x, w[0].y, w[1].x, w[1].y, color);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points(path: &[(f32, f32)]) -> Vec<Point> {
+        path.iter().map(|&(x, y)| Point::new(x, y)).collect()
+    }
+
+    const TRIANGLE: [(f32, f32); 4] = [(0.0, -1.0), (1.0, 0.8), (-1.0, 0.8), (0.0, -1.0)];
+    const ZIGZAG: [(f32, f32); 5] = [
+        (-1.0, -0.5),
+        (-0.5, 0.5),
+        (0.0, -0.5),
+        (0.5, 0.5),
+        (1.0, -0.5),
+    ];
+
+    fn recognizer() -> RuneRecognizer<u8> {
+        RuneRecognizer::new()
+            .with_template(Template::new(1, &TRIANGLE).unwrap())
+            .with_template(Template::new(2, &ZIGZAG).unwrap())
+    }
+
+    #[test]
+    fn resamples_evenly() {
+        let line = resample(&points(&[(0.0, 0.0), (9.0, 0.0)]), 10).unwrap();
+        assert_eq!(line.len(), 10);
+        for (i, p) in line.iter().enumerate() {
+            assert!((p.x - i as f32).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn short_strokes_are_rejected() {
+        assert!(resample(&[], RESAMPLE_POINTS).is_none());
+        assert!(resample(&points(&[(1.0, 1.0)]), RESAMPLE_POINTS).is_none());
+        assert!(Template::new(0, &[]).is_none());
+        assert!(Template::new(0, &[(3.0, 4.0)]).is_none());
+        assert!(Template::new(0, &[(3.0, 4.0), (3.0, 4.0)]).is_none());
+        let r = recognizer();
+        assert!(r.recognize(&[]).is_none());
+        assert!(r.recognize(&points(&[(5.0, 5.0)])).is_none());
+        assert!(r.recognize(&points(&[(5.0, 5.0), (5.0, 5.0)])).is_none());
+    }
+
+    #[test]
+    fn recognizes_scaled_and_moved_strokes() {
+        let r = recognizer();
+        let triangle: Vec<Point> = TRIANGLE
+            .iter()
+            .map(|&(x, y)| Point::new(100.0 + x * 30.0, 60.0 + y * 30.0))
+            .collect();
+        let found = r.recognize(&triangle).unwrap();
+        assert_eq!(found.output, 1);
+        assert!(found.score > 0.95);
+
+        let zigzag: Vec<Point> = ZIGZAG
+            .iter()
+            .map(|&(x, y)| Point::new(x * 50.0, y * 20.0 - 7.0))
+            .collect();
+        assert_eq!(r.recognize(&zigzag).unwrap().output, 2);
+    }
+
+    #[test]
+    fn recognizes_slightly_rotated_strokes() {
+        let r = recognizer();
+        let (sin, cos) = 0.2f32.sin_cos();
+        let tilted: Vec<Point> = TRIANGLE
+            .iter()
+            .map(|&(x, y)| Point::new(x * cos - y * sin, x * sin + y * cos))
+            .collect();
+        assert_eq!(r.recognize(&tilted).unwrap().output, 1);
+    }
+
+    #[test]
+    fn poor_matches_are_ignored() {
+        let r = recognizer().min_score(0.99);
+        let scribble = points(&[(0.0, 0.0), (5.0, 1.0), (1.0, 4.0), (6.0, 6.0), (0.0, 9.0)]);
+        assert!(r.recognize(&scribble).is_none());
+    }
+
+    #[test]
+    fn source_lists_the_normalized_points() {
+        let template = Template::new((), &TRIANGLE).unwrap();
+        let source = template.to_source("Spell::Fireball");
+        assert!(source.starts_with("Template::new(Spell::Fireball, &[("));
+        assert_eq!(source.matches("), (").count(), RESAMPLE_POINTS - 1);
+    }
+}
@@ -0,0 +1,471 @@
+use std::marker::PhantomData;
+
+use crate::actions::{Action, Actions};
+use crate::bitpack::{self, BitPack};
+use crate::clip::{push_clip, ClipRect};
+use crate::persistent::{LoadStatus, PersistentStore, Schema, HEADER_SLOTS, SLOTS};
+use crate::tic80::*;
+use crate::tic80_error::Tic80Error;
+
+pub const SAVE_SLOTS: usize = 3;
+/// 32 bit words of persistent memory given to each save slot.
+pub const SLOT_WORDS: usize = 64;
+/// Words after the save slots, for settings shared by all of them.
+pub const SETTINGS_WORDS: usize = SLOTS - HEADER_SLOTS - SAVE_SLOTS * SLOT_WORDS;
+const SETTINGS_START: usize = SAVE_SLOTS * SLOT_WORDS;
+/// Words each slot took before settings were stored.
+const OLD_SLOT_WORDS: usize = (SLOTS - HEADER_SLOTS) / SAVE_SLOTS;
+
+/// A [`Migration`](crate::persistent::Migration) for saves from before
+/// settings were stored, when the slots filled persistent memory. A packed
+/// save ends in zero padding, so each is cut down to [`SLOT_WORDS`] and the
+/// settings start out empty.
+pub fn migrate_to_settings_layout(slots: &mut [u32; SLOTS]) {
+    for slot in 0..SAVE_SLOTS {
+        let from = HEADER_SLOTS + slot * OLD_SLOT_WORDS;
+        slots.copy_within(from..from + SLOT_WORDS, HEADER_SLOTS + slot * SLOT_WORDS);
+    }
+    slots[HEADER_SLOTS + SETTINGS_START..].fill(0);
+}
+
+/// Summary of a save, shown on the slot selection screen.
+#[derive(BitPack, Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct SlotMeta {
+    /// Deepest floor reached.
+    pub floor: u8,
+    /// Character level.
+    pub level: u8,
+    /// Seconds played.
+    pub play_time: u32,
+    /// Unix time of the last save, from `tstamp`.
+    pub saved_at: u32,
+}
+
+/// One save slot's contents.
+#[derive(BitPack, Clone, Default, PartialEq, Eq, Debug)]
+pub struct SaveFile<T> {
+    pub meta: SlotMeta,
+    pub data: T,
+}
+
+/// [`SAVE_SLOTS`] bit-packed saves of the game's data `T` sharing one
+/// [`PersistentStore`], so the header checksum covers all of them.
+pub struct SaveSlots<T> {
+    store: PersistentStore,
+    _data: PhantomData<T>,
+}
+
+impl<T: BitPack> SaveSlots<T> {
+    const FITS: () = assert!(
+        <Option<SaveFile<T>> as BitPack>::BITS <= SLOT_WORDS * 32,
+        "save data doesn't fit in a save slot"
+    );
+
+    pub fn open(schema: Schema) -> (Self, LoadStatus) {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::FITS;
+        let (store, status) = PersistentStore::open(schema);
+        let slots = Self {
+            store,
+            _data: PhantomData,
+        };
+        (slots, status)
+    }
+
+    fn words(&self, slot: usize) -> Result<&[u32], Tic80Error> {
+        if slot >= SAVE_SLOTS {
+            return Err(Tic80Error::OutOfRange {
+                index: slot,
+                len: SAVE_SLOTS,
+            });
+        }
+        Ok(&self.store.slots()[slot * SLOT_WORDS..(slot + 1) * SLOT_WORDS])
+    }
+
+    fn words_mut(&mut self, slot: usize) -> Result<&mut [u32], Tic80Error> {
+        if slot >= SAVE_SLOTS {
+            return Err(Tic80Error::OutOfRange {
+                index: slot,
+                len: SAVE_SLOTS,
+            });
+        }
+        Ok(&mut self.store.slots_mut()[slot * SLOT_WORDS..(slot + 1) * SLOT_WORDS])
+    }
+
+    /// The save in `slot`, `None` when it's empty.
+    pub fn load(&self, slot: usize) -> Result<Option<SaveFile<T>>, Tic80Error> {
+        bitpack::unpack_words(self.words(slot)?)
+    }
+
+    /// Just the metadata of `slot`, for listing saves.
+    pub fn meta(&self, slot: usize) -> Option<SlotMeta> {
+        self.load(slot).ok().flatten().map(|file| file.meta)
+    }
+
+    pub fn is_empty(&self, slot: usize) -> bool {
+        self.meta(slot).is_none()
+    }
+
+    /// Save `data` to `slot` and write persistent memory. The timestamp in
+    /// `meta` is set to now.
+    pub fn save(&mut self, slot: usize, mut meta: SlotMeta, data: T) -> Result<(), Tic80Error> {
+        meta.saved_at = tstamp();
+        let file = Some(SaveFile { meta, data });
+        bitpack::pack_words(&file, self.words_mut(slot)?)?;
+        self.store.save();
+        Ok(())
+    }
+
+    /// Copy the save in `from` over `to`.
+    pub fn copy(&mut self, from: usize, to: usize) -> Result<(), Tic80Error> {
+        let words: Vec<u32> = self.words(from)?.to_vec();
+        self.words_mut(to)?.copy_from_slice(&words);
+        self.store.save();
+        Ok(())
+    }
+
+    pub fn erase(&mut self, slot: usize) -> Result<(), Tic80Error> {
+        self.words_mut(slot)?.fill(0);
+        self.store.save();
+        Ok(())
+    }
+
+    /// The settings stored with [`SaveSlots::save_settings`], `None` if
+    /// there are none yet.
+    pub fn load_settings<S: BitPack>(&self) -> Result<Option<S>, Tic80Error> {
+        bitpack::unpack_words(&self.store.slots()[SETTINGS_START..])
+    }
+
+    pub fn save_settings<S: BitPack>(&mut self, settings: S) -> Result<(), Tic80Error> {
+        bitpack::pack_words(&Some(settings), &mut self.store.slots_mut()[SETTINGS_START..])?;
+        self.store.save();
+        Ok(())
+    }
+}
+
+/// Format seconds as `H:MM:SS`.
+fn format_play_time(seconds: u32) -> String {
+    format!(
+        "{}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Format a Unix timestamp as `YYYY-MM-DD HH:MM` (UTC).
+fn format_timestamp(timestamp: u32) -> String {
+    let days = (timestamp / 86400) as i64;
+    let minutes = timestamp % 86400 / 60;
+    // Days to civil date, from Howard Hinnant's date algorithms.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{}-{:02}-{:02} {:02}:{:02}",
+        year,
+        month,
+        day,
+        minutes / 60,
+        minutes % 60
+    )
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum PickerMode {
+    Select,
+    /// Choosing where to copy slot `from`.
+    CopyTo {
+        from: usize,
+    },
+    ConfirmErase,
+}
+
+/// The title screen list of save slots, read through [`Actions`]. Up and
+/// down pick a slot, cast plays it (starting a new game when empty),
+/// inventory copies it to another slot and pause erases it after a
+/// confirmation.
+pub struct SlotPicker {
+    cursor: usize,
+    mode: PickerMode,
+}
+
+impl Default for SlotPicker {
+    fn default() -> Self {
+        Self {
+            cursor: 0,
+            mode: PickerMode::Select,
+        }
+    }
+}
+
+const PLAY: Action = Action::CastSpell;
+const COPY: Action = Action::OpenInventory;
+const ERASE: Action = Action::Pause;
+/// Backs out of copying or erasing.
+const CANCEL: Action = Action::OpenInventory;
+
+/// The first input bound to `action`, for help text.
+fn label(actions: &Actions, action: Action) -> String {
+    actions
+        .bindings()
+        .get(action)
+        .first()
+        .map_or_else(|| action.name().to_string(), |binding| binding.name())
+}
+
+impl SlotPicker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// Handle input, returns the slot to play once one is chosen.
+    pub fn update<T: BitPack>(
+        &mut self,
+        actions: &Actions,
+        saves: &mut SaveSlots<T>,
+    ) -> Option<usize> {
+        let up = actions.repeat(Action::MoveUp, 15, 6);
+        let down = actions.repeat(Action::MoveDown, 15, 6);
+        if up {
+            self.cursor = (self.cursor + SAVE_SLOTS - 1) % SAVE_SLOTS;
+        }
+        if down {
+            self.cursor = (self.cursor + 1) % SAVE_SLOTS;
+        }
+        match self.mode {
+            PickerMode::Select => {
+                if actions.just_pressed(PLAY) {
+                    return Some(self.cursor);
+                }
+                let occupied = !saves.is_empty(self.cursor);
+                if actions.just_pressed(COPY) && occupied {
+                    self.mode = PickerMode::CopyTo { from: self.cursor };
+                } else if actions.just_pressed(ERASE) && occupied {
+                    self.mode = PickerMode::ConfirmErase;
+                }
+            }
+            PickerMode::CopyTo { from } => {
+                if actions.just_pressed(PLAY) && self.cursor != from {
+                    if let Err(e) = saves.copy(from, self.cursor) {
+                        trace(format!("Copy failed: {}\0", e), None);
+                    }
+                    self.mode = PickerMode::Select;
+                } else if actions.just_pressed(CANCEL) {
+                    self.cursor = from;
+                    self.mode = PickerMode::Select;
+                }
+            }
+            PickerMode::ConfirmErase => {
+                if actions.just_pressed(PLAY) {
+                    if let Err(e) = saves.erase(self.cursor) {
+                        trace(format!("Erase failed: {}\0", e), None);
+                    }
+                    self.mode = PickerMode::Select;
+                } else if actions.just_pressed(CANCEL) || up || down {
+                    self.mode = PickerMode::Select;
+                }
+            }
+        }
+        None
+    }
+
+    pub fn draw<T: BitPack>(&self, actions: &Actions, saves: &SaveSlots<T>, title: &str) {
+        cls(0);
+        // The default font is 6 pixels wide.
+        Print::default()
+            .x((240 - title.len() as i32 * 6) / 2)
+            .y(8)
+            .color(12)
+            .print(format!("{}\0", title));
+
+        for slot in 0..SAVE_SLOTS {
+            let y = 24 + slot as i32 * 30;
+            let selected = slot == self.cursor;
+            let copying_from = matches!(self.mode, PickerMode::CopyTo { from } if from == slot);
+            rect(20, y, 200, 26, if selected { 8 } else { 15 });
+            rectb(
+                20,
+                y,
+                200,
+                26,
+                if copying_from {
+                    4
+                } else if selected {
+                    12
+                } else {
+                    14
+                },
+            );
+            let _panel = push_clip(ClipRect::new(21, y + 1, 198, 24));
+            Print::default()
+                .x(26)
+                .y(y + 4)
+                .color(12)
+                .print(format!("SLOT {}\0", slot + 1));
+            match saves.meta(slot) {
+                Some(meta) => {
+                    Print::default()
+                        .x(80)
+                        .y(y + 4)
+                        .color(12)
+                        .print(format!("FLOOR {}  LV {}\0", meta.floor, meta.level));
+                    Print::default()
+                        .x(26)
+                        .y(y + 15)
+                        .color(13)
+                        .smallfont(true)
+                        .print(format!(
+                            "{}   {}\0",
+                            format_play_time(meta.play_time),
+                            format_timestamp(meta.saved_at)
+                        ));
+                }
+                None => {
+                    Print::default()
+                        .x(80)
+                        .y(y + 4)
+                        .color(13)
+                        .print("- EMPTY -\0");
+                }
+            }
+        }
+
+        let help = match self.mode {
+            PickerMode::Select => format!(
+                "{} PLAY   {} COPY   {} ERASE\0",
+                label(actions, PLAY),
+                label(actions, COPY),
+                label(actions, ERASE)
+            ),
+            PickerMode::CopyTo { .. } => format!(
+                "COPY TO WHICH SLOT?  {} OK   {} CANCEL\0",
+                label(actions, PLAY),
+                label(actions, CANCEL)
+            ),
+            PickerMode::ConfirmErase => format!(
+                "ERASE THIS SLOT?  {} YES   {} NO\0",
+                label(actions, PLAY),
+                label(actions, CANCEL)
+            ),
+        };
+        Print::default()
+            .x(20)
+            .y(122)
+            .color(13)
+            .smallfont(true)
+            .print(help);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCHEMA: Schema = Schema {
+        magic: 0x51a7_0001,
+        version: 0,
+        first_version: 0,
+        migrations: &[],
+    };
+
+    fn empty_slots() -> SaveSlots<[u16; 3]> {
+        let (store, _) = PersistentStore::from_slots(SCHEMA, [0; SLOTS]);
+        SaveSlots {
+            store,
+            _data: PhantomData,
+        }
+    }
+
+    #[test]
+    fn formats_play_time() {
+        assert_eq!(format_play_time(0), "0:00:00");
+        assert_eq!(format_play_time(3 * 3600 + 7 * 60 + 9), "3:07:09");
+        assert_eq!(format_play_time(100 * 3600), "100:00:00");
+    }
+
+    #[test]
+    fn formats_timestamps() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00");
+        assert_eq!(format_timestamp(951_782_400), "2000-02-29 00:00");
+        assert_eq!(format_timestamp(1_700_000_000), "2023-11-14 22:13");
+        assert_eq!(format_timestamp(u32::MAX), "2106-02-07 06:28");
+    }
+
+    #[test]
+    fn slots_start_empty() {
+        let saves = empty_slots();
+        for slot in 0..SAVE_SLOTS {
+            assert!(saves.is_empty(slot));
+            assert_eq!(saves.load(slot).unwrap(), None);
+        }
+        assert_eq!(saves.load_settings::<u32>().unwrap(), None);
+    }
+
+    #[test]
+    fn slots_are_independent() {
+        let mut saves = empty_slots();
+        let file = Some(SaveFile {
+            meta: SlotMeta {
+                floor: 4,
+                level: 9,
+                play_time: 600,
+                saved_at: 0,
+            },
+            data: [1, 2, 3],
+        });
+        bitpack::pack_words(&file, saves.words_mut(1).unwrap()).unwrap();
+        assert!(saves.is_empty(0));
+        assert_eq!(saves.load(1).unwrap(), file);
+        assert!(saves.is_empty(2));
+        assert_eq!(saves.meta(1).unwrap().floor, 4);
+    }
+
+    #[test]
+    fn migrates_full_size_slots() {
+        let mut slots = [0; SLOTS];
+        for slot in 0..SAVE_SLOTS {
+            let start = HEADER_SLOTS + slot * OLD_SLOT_WORDS;
+            slots[start] = slot as u32 + 1;
+            slots[start + SLOT_WORDS - 1] = 0xff;
+        }
+        slots[0] = 7;
+        migrate_to_settings_layout(&mut slots);
+        assert_eq!(slots[0], 7);
+        for slot in 0..SAVE_SLOTS {
+            let start = HEADER_SLOTS + slot * SLOT_WORDS;
+            assert_eq!(slots[start], slot as u32 + 1);
+            assert_eq!(slots[start + SLOT_WORDS - 1], 0xff);
+        }
+        assert!(slots[HEADER_SLOTS + SETTINGS_START..].iter().all(|&w| w == 0));
+    }
+
+    #[test]
+    fn settings_round_trip() {
+        let mut saves = empty_slots();
+        let words = &mut saves.store.slots_mut()[SETTINGS_START..];
+        bitpack::pack_words(&Some(0xbeef_u32), words).unwrap();
+        assert_eq!(saves.load_settings::<u32>().unwrap(), Some(0xbeef));
+        assert!(saves.is_empty(SAVE_SLOTS - 1));
+    }
+
+    #[test]
+    fn out_of_range_slots_are_errors() {
+        let saves = empty_slots();
+        assert!(matches!(
+            saves.load(SAVE_SLOTS),
+            Err(Tic80Error::OutOfRange { index: 3, len: 3 })
+        ));
+        assert!(saves.meta(SAVE_SLOTS).is_none());
+    }
+}
@@ -0,0 +1,398 @@
+use heapless::Vec as Vector;
+
+use crate::music_data::{Pattern, PatternRow, Track, PATTERN_COUNT, TRACK_CHANNELS};
+use crate::music_player::MusicStatus;
+use crate::rng::Rng;
+use crate::tic80::*;
+use crate::tic80_error::Tic80Error;
+
+/// Semitone steps of a scale from its root.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Scale {
+    Major,
+    Minor,
+    HarmonicMinor,
+    Dorian,
+    MinorPentatonic,
+}
+
+impl Scale {
+    pub fn intervals(self) -> &'static [u8] {
+        match self {
+            Scale::Major => &[0, 2, 4, 5, 7, 9, 11],
+            Scale::Minor => &[0, 2, 3, 5, 7, 8, 10],
+            Scale::HarmonicMinor => &[0, 2, 3, 5, 7, 8, 11],
+            Scale::Dorian => &[0, 2, 3, 5, 7, 9, 10],
+            Scale::MinorPentatonic => &[0, 3, 5, 7, 10],
+        }
+    }
+
+    /// Semitones above the root of scale degree `degree`, which may go past one octave.
+    pub fn semitones(self, degree: i32) -> i32 {
+        let intervals = self.intervals();
+        let len = intervals.len() as i32;
+        degree.div_euclid(len) * 12 + intervals[degree.rem_euclid(len) as usize] as i32
+    }
+}
+
+/// A part of the arrangement that can be faded in and out with intensity.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LayerKind {
+    Percussion,
+    Bass,
+    Melody,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Layer {
+    pub kind: LayerKind,
+    /// Music channel 0..3 the layer plays on.
+    pub channel: usize,
+    /// Sound effect used as the instrument.
+    pub sfx: u8,
+    /// The layer plays while the intensity is at least this.
+    pub threshold: f32,
+}
+
+impl Layer {
+    /// A layer on `channel` 0..3.
+    pub fn new(
+        kind: LayerKind,
+        channel: usize,
+        sfx: u8,
+        threshold: f32,
+    ) -> Result<Self, Tic80Error> {
+        check_channel(channel)?;
+        Ok(Self {
+            kind,
+            channel,
+            sfx,
+            threshold,
+        })
+    }
+}
+
+fn check_channel(channel: usize) -> Result<(), Tic80Error> {
+    if channel >= TRACK_CHANNELS {
+        return Err(Tic80Error::OutOfRange {
+            index: channel,
+            len: TRACK_CHANNELS,
+        });
+    }
+    Ok(())
+}
+
+/// Writes generated patterns into a looping two frame track. While one frame
+/// plays the other is rewritten with the next chord and the current
+/// intensity, so the music keeps looping without gaps.
+pub struct Sequencer {
+    rng: Rng,
+    track: usize,
+    /// First of the 8 patterns the sequencer owns, two frames of four channels.
+    first_pattern: usize,
+    scale: Scale,
+    root: u8,
+    progression: Vector<i8, 16>,
+    layers: Vector<Layer, TRACK_CHANNELS>,
+    rows: u8,
+    tempo: i32,
+    speed: i32,
+    intensity: f32,
+    chord: usize,
+    playing_frame: Option<u8>,
+}
+
+impl Sequencer {
+    /// `progression` is a list of scale degrees, one chord per frame. Only
+    /// the first 16 chords are used, an empty progression stays on the root.
+    pub fn new(seed: u32, scale: Scale, root: u8, progression: &[i8]) -> Self {
+        let mut chords: Vector<i8, 16> = Vector::new();
+        let len = progression.len().min(chords.capacity());
+        // Okay to unwrap, the slice is cut to the capacity.
+        chords.extend_from_slice(&progression[..len]).unwrap();
+        if chords.is_empty() {
+            chords.push(0).unwrap();
+        }
+        Self {
+            rng: Rng::new(seed),
+            track: 7,
+            first_pattern: PATTERN_COUNT - 2 * TRACK_CHANNELS,
+            scale,
+            root: root % 12,
+            progression: chords,
+            layers: Vector::new(),
+            rows: 32,
+            tempo: 150,
+            speed: 6,
+            intensity: 0.0,
+            chord: 0,
+            playing_frame: None,
+        }
+    }
+
+    /// Add a layer, at most one per channel.
+    pub fn with_layer(mut self, layer: Layer) -> Result<Self, Tic80Error> {
+        check_channel(layer.channel)?;
+        if self.layers.iter().any(|l| l.channel == layer.channel) {
+            return Err(Tic80Error::InvalidData(
+                "a layer already plays on this channel",
+            ));
+        }
+        // Okay to unwrap, there's room for a layer on every channel.
+        self.layers.push(layer).unwrap();
+        Ok(self)
+    }
+
+    /// Use `track` and patterns `first_pattern..first_pattern + 8`.
+    pub fn with_slots(mut self, track: usize, first_pattern: usize) -> Self {
+        self.track = track;
+        self.first_pattern = first_pattern;
+        self
+    }
+
+    pub fn with_timing(mut self, rows: u8, tempo: i32, speed: i32) -> Self {
+        self.rows = rows.clamp(4, 64);
+        self.tempo = tempo;
+        self.speed = speed;
+        self
+    }
+
+    /// Game intensity 0.0..=1.0, e.g. from enemies nearby or low health.
+    /// Takes effect from the next generated frame.
+    pub fn set_intensity(&mut self, intensity: f32) {
+        self.intensity = intensity.clamp(0.0, 1.0);
+    }
+
+    pub fn intensity(&self) -> f32 {
+        self.intensity
+    }
+
+    fn pattern_id(&self, frame: usize, channel: usize) -> usize {
+        self.first_pattern + frame * TRACK_CHANNELS + channel
+    }
+
+    /// Write the track, generate both frames and start looping playback.
+    pub fn start(&mut self) -> Result<(), Tic80Error> {
+        let mut track = Track {
+            tempo: self.tempo,
+            speed: self.speed,
+            rows: self.rows,
+            ..Track::default()
+        };
+        for frame in 0..2 {
+            for channel in 0..TRACK_CHANNELS {
+                track.frames[frame][channel] = Some(self.pattern_id(frame, channel) as u8);
+            }
+        }
+        track.write(self.track)?;
+        self.chord = 0;
+        self.generate_frame(0)?;
+        self.generate_frame(1)?;
+        self.playing_frame = Some(0);
+        Music::default()
+            .track(self.track as i32)
+            .looping(true)
+            .play();
+        Ok(())
+    }
+
+    /// Call once per frame with the current music status. When the play
+    /// head enters a frame the other one is regenerated.
+    pub fn update(&mut self, status: &MusicStatus) -> Result<(), Tic80Error> {
+        if status.track != Some(self.track as u8) {
+            return Ok(());
+        }
+        if self.playing_frame != Some(status.frame) {
+            self.playing_frame = Some(status.frame);
+            self.generate_frame(((status.frame + 1) % 2) as usize)?;
+        }
+        Ok(())
+    }
+
+    /// Fill one frame's patterns from the next chord of the progression.
+    fn generate_frame(&mut self, frame: usize) -> Result<(), Tic80Error> {
+        let degree = self.progression[self.chord % self.progression.len()] as i32;
+        self.chord += 1;
+        for channel in 0..TRACK_CHANNELS {
+            let layer = self.layers.iter().find(|l| l.channel == channel).copied();
+            let pattern = match layer {
+                Some(layer) if self.intensity >= layer.threshold => {
+                    self.generate_layer(&layer, degree)
+                }
+                _ => Pattern::default(),
+            };
+            pattern.write(self.pattern_id(frame, channel))?;
+        }
+        Ok(())
+    }
+
+    fn generate_layer(&mut self, layer: &Layer, degree: i32) -> Pattern {
+        let mut pattern = Pattern::default();
+        let rows = self.rows as usize;
+        let beat = (rows / 4).max(1);
+        let intensity = self.intensity;
+        match layer.kind {
+            LayerKind::Percussion => {
+                for row in (0..rows).step_by((beat / 2).max(1)) {
+                    let (note, octave) = if row % (beat * 2) == 0 {
+                        (0, 2) // kick
+                    } else if row % beat == 0 {
+                        (4, 4) // snare
+                    } else if self.rng.chance(intensity) {
+                        (0, 6) // hat
+                    } else {
+                        continue;
+                    };
+                    pattern.rows[row] = PatternRow::play(note, octave, layer.sfx);
+                }
+            }
+            LayerKind::Bass => {
+                let step = (if intensity > 0.6 { beat / 2 } else { beat }).max(1);
+                for row in (0..rows).step_by(step) {
+                    // Alternate root and fifth when the music gets busier.
+                    let offset = if intensity > 0.6 && (row / step) % 2 == 1 {
+                        4
+                    } else {
+                        0
+                    };
+                    pattern.rows[row] = self.row_for(degree + offset, 2, layer.sfx);
+                }
+            }
+            LayerKind::Melody => {
+                let step = (beat / 2).max(1);
+                let mut position = degree + self.rng.range(0, 3) * 2;
+                for row in (0..rows).step_by(step) {
+                    if !self.rng.chance(0.4 + 0.5 * intensity) {
+                        continue;
+                    }
+                    // Chord tones on the beat, stepwise motion in between.
+                    position = if row % beat == 0 {
+                        degree + self.rng.range(0, 3) * 2
+                    } else {
+                        position + self.rng.range(-1, 2)
+                    };
+                    pattern.rows[row] = self.row_for(position, 4, layer.sfx);
+                }
+            }
+        }
+        pattern
+    }
+
+    fn row_for(&self, degree: i32, octave: i32, sfx: u8) -> PatternRow {
+        let semitone = self.root as i32 + self.scale.semitones(degree) + octave * 12;
+        let octave = semitone.div_euclid(12).clamp(0, 7);
+        PatternRow::play(semitone.rem_euclid(12) as u8, octave as u8, sfx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::music_data::RowNote;
+
+    fn sequencer(seed: u32) -> Sequencer {
+        Sequencer::new(seed, Scale::Minor, 9, &[0, 3, 4])
+            .with_layer(Layer::new(LayerKind::Percussion, 0, 1, 0.0).unwrap())
+            .unwrap()
+            .with_layer(Layer::new(LayerKind::Bass, 1, 2, 0.0).unwrap())
+            .unwrap()
+            .with_layer(Layer::new(LayerKind::Melody, 2, 3, 0.5).unwrap())
+            .unwrap()
+            .with_timing(32, 120, 6)
+    }
+
+    fn notes(pattern: &Pattern) -> Vec<(usize, RowNote)> {
+        pattern
+            .rows
+            .iter()
+            .enumerate()
+            .filter(|(_, row)| row.note != RowNote::Empty)
+            .map(|(i, row)| (i, row.note))
+            .collect()
+    }
+
+    #[test]
+    fn scale_degrees_wrap_into_octaves() {
+        assert_eq!(Scale::Major.semitones(0), 0);
+        assert_eq!(Scale::Major.semitones(2), 4);
+        assert_eq!(Scale::Major.semitones(7), 12);
+        assert_eq!(Scale::Minor.semitones(-1), -2);
+        assert_eq!(Scale::MinorPentatonic.semitones(6), 15);
+    }
+
+    #[test]
+    fn rows_are_in_key() {
+        let sequencer = sequencer(1);
+        // A minor, degree 2 is C above the A.
+        assert_eq!(
+            sequencer.row_for(2, 4, 0).note,
+            RowNote::Play { note: 0, octave: 5 }
+        );
+        assert_eq!(
+            sequencer.row_for(0, 2, 0).note,
+            RowNote::Play { note: 9, octave: 2 }
+        );
+    }
+
+    #[test]
+    fn percussion_keeps_kick_and_snare() {
+        let mut sequencer = sequencer(1);
+        let layer = Layer::new(LayerKind::Percussion, 0, 1, 0.0).unwrap();
+        let pattern = sequencer.generate_layer(&layer, 0);
+        let kick = RowNote::Play { note: 0, octave: 2 };
+        let snare = RowNote::Play { note: 4, octave: 4 };
+        assert_eq!(pattern.rows[0].note, kick);
+        assert_eq!(pattern.rows[8].note, snare);
+        assert_eq!(pattern.rows[16].note, kick);
+        // No hats at zero intensity.
+        assert_eq!(notes(&pattern).len(), 4);
+    }
+
+    #[test]
+    fn busier_bass_at_high_intensity() {
+        let layer = Layer::new(LayerKind::Bass, 1, 2, 0.0).unwrap();
+        let mut calm = sequencer(1);
+        let mut busy = sequencer(1);
+        busy.set_intensity(1.0);
+        assert_eq!(notes(&calm.generate_layer(&layer, 0)).len(), 4);
+        assert_eq!(notes(&busy.generate_layer(&layer, 0)).len(), 8);
+    }
+
+    #[test]
+    fn same_seed_same_melody() {
+        let layer = Layer::new(LayerKind::Melody, 2, 3, 0.0).unwrap();
+        let a = sequencer(9).generate_layer(&layer, 3);
+        let b = sequencer(9).generate_layer(&layer, 3);
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn one_layer_per_channel() {
+        let layer = Layer::new(LayerKind::Bass, 3, 2, 0.0).unwrap();
+        let sequencer = sequencer(1).with_layer(layer).unwrap();
+        assert!(matches!(
+            sequencer.with_layer(layer),
+            Err(Tic80Error::InvalidData(_))
+        ));
+    }
+
+    #[test]
+    fn channels_past_the_track_are_errors() {
+        assert!(matches!(
+            Layer::new(LayerKind::Bass, 4, 2, 0.0),
+            Err(Tic80Error::OutOfRange { index: 4, len: 4 })
+        ));
+        let layer = Layer {
+            channel: 5,
+            ..Layer::new(LayerKind::Bass, 0, 2, 0.0).unwrap()
+        };
+        assert!(sequencer(1).with_layer(layer).is_err());
+    }
+
+    #[test]
+    fn long_progressions_are_cut() {
+        let sequencer = Sequencer::new(1, Scale::Major, 0, &[1; 20]);
+        assert_eq!(sequencer.progression.len(), 16);
+        assert_eq!(Sequencer::new(1, Scale::Major, 0, &[]).progression, [0]);
+    }
+}
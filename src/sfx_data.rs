@@ -0,0 +1,270 @@
+use crate::tic80::*;
+use crate::tic80_error::Tic80Error;
+
+pub const SFX_COUNT: usize = 64;
+pub const SFX_TICKS: usize = 30;
+pub const SFX_SIZE: usize = 66;
+const MAX_VOLUME: u8 = 15;
+
+/// One tick of a sound effect's envelopes.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct SfxTick {
+    /// 0..15, 15 is loudest. TIC-80 stores it inverted.
+    pub volume: u8,
+    /// Waveform id 0..15.
+    pub wave: u8,
+    /// Semitone offset 0..15.
+    pub arpeggio: u8,
+    /// Pitch offset -8..7.
+    pub pitch: i8,
+}
+
+/// Loop region of one envelope, in ticks. A size of 0 means no loop.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct SfxLoop {
+    pub start: u8,
+    pub size: u8,
+}
+
+/// A sound effect as stored in a 66 byte record of the `SFX` region.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SfxData {
+    pub ticks: [SfxTick; SFX_TICKS],
+    /// 0..7
+    pub octave: u8,
+    /// Note 0 = C .. 11 = B.
+    pub note: u8,
+    /// -4..3
+    pub speed: i8,
+    /// Multiply the pitch envelope by 16.
+    pub pitch16x: bool,
+    /// Play the arpeggio envelope in reverse.
+    pub reverse: bool,
+    pub stereo_left: bool,
+    pub stereo_right: bool,
+    pub wave_loop: SfxLoop,
+    pub volume_loop: SfxLoop,
+    pub arpeggio_loop: SfxLoop,
+    pub pitch_loop: SfxLoop,
+}
+
+impl Default for SfxData {
+    fn default() -> Self {
+        Self {
+            ticks: [SfxTick::default(); SFX_TICKS],
+            octave: 4,
+            note: 0,
+            speed: 0,
+            pitch16x: false,
+            reverse: false,
+            stereo_left: false,
+            stereo_right: false,
+            wave_loop: SfxLoop::default(),
+            volume_loop: SfxLoop::default(),
+            arpeggio_loop: SfxLoop::default(),
+            pitch_loop: SfxLoop::default(),
+        }
+    }
+}
+
+/// Sign extend the low `bits` of `value`.
+fn signed(value: u8, bits: u32) -> i8 {
+    let shift = 8 - bits;
+    ((value << shift) as i8) >> shift
+}
+
+fn unsigned(value: i8, bits: u32) -> u8 {
+    (value as u8) & ((1 << bits) - 1)
+}
+
+impl SfxLoop {
+    fn from_byte(byte: u8) -> Self {
+        Self {
+            start: byte & 0x0f,
+            size: byte >> 4,
+        }
+    }
+
+    fn to_byte(self) -> u8 {
+        (self.start & 0x0f) | (self.size << 4)
+    }
+}
+
+impl SfxData {
+    pub fn from_bytes(bytes: &[u8; SFX_SIZE]) -> Self {
+        let mut ticks = [SfxTick::default(); SFX_TICKS];
+        for (tick, data) in ticks.iter_mut().zip(bytes.chunks(2)) {
+            *tick = SfxTick {
+                volume: MAX_VOLUME - (data[0] & 0x0f),
+                wave: data[0] >> 4,
+                arpeggio: data[1] & 0x0f,
+                pitch: signed(data[1] >> 4, 4),
+            };
+        }
+        let flags = bytes[60];
+        let more = bytes[61];
+        Self {
+            ticks,
+            octave: flags & 0x07,
+            pitch16x: flags & 0x08 != 0,
+            speed: signed((flags >> 4) & 0x07, 3),
+            reverse: flags & 0x80 != 0,
+            note: more & 0x0f,
+            stereo_left: more & 0x10 != 0,
+            stereo_right: more & 0x20 != 0,
+            wave_loop: SfxLoop::from_byte(bytes[62]),
+            volume_loop: SfxLoop::from_byte(bytes[63]),
+            arpeggio_loop: SfxLoop::from_byte(bytes[64]),
+            pitch_loop: SfxLoop::from_byte(bytes[65]),
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; SFX_SIZE] {
+        let mut bytes = [0; SFX_SIZE];
+        for (tick, data) in self.ticks.iter().zip(bytes.chunks_mut(2)) {
+            data[0] = (MAX_VOLUME - tick.volume.min(MAX_VOLUME)) | (tick.wave << 4);
+            data[1] = (tick.arpeggio & 0x0f) | (unsigned(tick.pitch, 4) << 4);
+        }
+        bytes[60] = (self.octave & 0x07)
+            | (self.pitch16x as u8) << 3
+            | unsigned(self.speed, 3) << 4
+            | (self.reverse as u8) << 7;
+        bytes[61] =
+            (self.note & 0x0f) | (self.stereo_left as u8) << 4 | (self.stereo_right as u8) << 5;
+        bytes[62] = self.wave_loop.to_byte();
+        bytes[63] = self.volume_loop.to_byte();
+        bytes[64] = self.arpeggio_loop.to_byte();
+        bytes[65] = self.pitch_loop.to_byte();
+        bytes
+    }
+
+    /// Read sound effect `id` from `SFX`.
+    pub fn read(id: usize) -> Result<Self, Tic80Error> {
+        let offset = sfx_offset(id)?;
+        let mut bytes = [0; SFX_SIZE];
+        unsafe {
+            let sfx = &*SFX;
+            bytes.copy_from_slice(&sfx[offset..offset + SFX_SIZE]);
+        }
+        Ok(Self::from_bytes(&bytes))
+    }
+
+    /// Write to sound effect `id` in `SFX`, it can then be played with [`Sfx`].
+    pub fn write(&self, id: usize) -> Result<(), Tic80Error> {
+        let offset = sfx_offset(id)?;
+        unsafe {
+            let sfx = &mut *SFX;
+            sfx[offset..offset + SFX_SIZE].copy_from_slice(&self.to_bytes());
+        }
+        Ok(())
+    }
+
+    /// Set the volume envelope from the start, extra values are ignored.
+    pub fn volumes(&mut self, values: &[u8]) -> &mut Self {
+        for (tick, &value) in self.ticks.iter_mut().zip(values) {
+            tick.volume = value.min(MAX_VOLUME);
+        }
+        self
+    }
+
+    /// Set the waveform envelope from the start, extra values are ignored.
+    pub fn waves(&mut self, values: &[u8]) -> &mut Self {
+        for (tick, &value) in self.ticks.iter_mut().zip(values) {
+            tick.wave = value & 0x0f;
+        }
+        self
+    }
+
+    /// Set the arpeggio envelope from the start, extra values are ignored.
+    pub fn arpeggios(&mut self, values: &[u8]) -> &mut Self {
+        for (tick, &value) in self.ticks.iter_mut().zip(values) {
+            tick.arpeggio = value & 0x0f;
+        }
+        self
+    }
+
+    /// Set the pitch envelope from the start, extra values are ignored.
+    pub fn pitches(&mut self, values: &[i8]) -> &mut Self {
+        for (tick, &value) in self.ticks.iter_mut().zip(values) {
+            tick.pitch = value.clamp(-8, 7);
+        }
+        self
+    }
+
+    /// Use the same waveform for every tick.
+    pub fn wave(&mut self, wave: u8) -> &mut Self {
+        for tick in self.ticks.iter_mut() {
+            tick.wave = wave & 0x0f;
+        }
+        self
+    }
+}
+
+fn sfx_offset(id: usize) -> Result<usize, Tic80Error> {
+    if id >= SFX_COUNT {
+        return Err(Tic80Error::OutOfRange {
+            index: id,
+            len: SFX_COUNT,
+        });
+    }
+    Ok(id * SFX_SIZE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+
+    #[test]
+    fn encodes_the_record_layout() {
+        let mut sfx = SfxData {
+            octave: 5,
+            note: 9,
+            speed: -2,
+            pitch16x: true,
+            stereo_right: true,
+            volume_loop: SfxLoop { start: 2, size: 3 },
+            ..Default::default()
+        };
+        sfx.volumes(&[15, 10]).waves(&[3]).arpeggios(&[0, 4]).pitches(&[-1, 7]);
+        let bytes = sfx.to_bytes();
+        // Volume is stored inverted in the low nibble, the wave above it.
+        assert_eq!(bytes[0], 0x30);
+        assert_eq!(bytes[1], 0xf0);
+        assert_eq!(bytes[2], 0x05);
+        assert_eq!(bytes[3], 0x74);
+        // Silent ticks store volume 15.
+        assert_eq!(bytes[4], 0x0f);
+        assert_eq!(bytes[60], 0x6d);
+        assert_eq!(bytes[61], 0x29);
+        assert_eq!(bytes[63], 0x32);
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let mut rng = Rng::new(7);
+        for _ in 0..100 {
+            let mut bytes = [0; SFX_SIZE];
+            for byte in bytes.iter_mut() {
+                *byte = rng.range(0, 256) as u8;
+            }
+            // The top two bits of the note byte are unused.
+            bytes[61] &= 0x3f;
+            assert_eq!(SfxData::from_bytes(&bytes).to_bytes(), bytes);
+        }
+    }
+
+    #[test]
+    fn signed_fields_are_sign_extended() {
+        assert_eq!(signed(0x8, 4), -8);
+        assert_eq!(signed(0x7, 4), 7);
+        assert_eq!(signed(0x4, 3), -4);
+        assert_eq!(unsigned(-1, 3), 0x7);
+    }
+
+    #[test]
+    fn sfx_ids_are_checked() {
+        assert_eq!(sfx_offset(63).unwrap(), 63 * SFX_SIZE);
+        assert!(sfx_offset(SFX_COUNT).is_err());
+    }
+}
@@ -0,0 +1,344 @@
+use crate::rng::Rng;
+use crate::sfx_data::{SfxData, SfxLoop, SFX_TICKS};
+use crate::tic80_error::Tic80Error;
+use crate::waveform::Waveform;
+
+/// Starting points for generated sound effects.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SfxPreset {
+    Pickup,
+    Hurt,
+    Explosion,
+    Laser,
+    Jump,
+    Magic,
+}
+
+impl SfxPreset {
+    pub const ALL: [SfxPreset; 6] = [
+        SfxPreset::Pickup,
+        SfxPreset::Hurt,
+        SfxPreset::Explosion,
+        SfxPreset::Laser,
+        SfxPreset::Jump,
+        SfxPreset::Magic,
+    ];
+}
+
+/// Shape of the generated waveform.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum WaveKind {
+    Square(f32),
+    Saw,
+    Triangle,
+    Sine,
+    Noise,
+    Harmonics([f32; 4]),
+}
+
+/// Parameters a sound is rendered from, in the spirit of sfxr.
+/// Times are in sfx ticks, pitch is in the sfx pitch envelope's units.
+#[derive(Clone, PartialEq, Debug)]
+pub struct SfxParams {
+    pub wave: WaveKind,
+    pub note: u8,
+    pub octave: u8,
+    pub speed: i8,
+    pub attack: u8,
+    pub sustain: u8,
+    pub decay: u8,
+    /// Peak volume 0..15.
+    pub volume: u8,
+    pub start_pitch: f32,
+    /// Pitch change per tick.
+    pub slide: f32,
+    /// Scale pitch by 16 for wide sweeps.
+    pub pitch16x: bool,
+    /// Vibrato depth in pitch units, 0 disables.
+    pub vibrato_depth: f32,
+    /// Vibrato period in ticks.
+    pub vibrato_period: u8,
+    /// Semitones added from `arpeggio_tick` on.
+    pub arpeggio: u8,
+    pub arpeggio_tick: u8,
+    /// Loop the volume envelope's sustain part.
+    pub repeat: bool,
+}
+
+impl SfxParams {
+    /// Render the sound effect and its waveform. `seed` only affects noise.
+    pub fn render(&self, seed: u32) -> GeneratedSfx {
+        let mut sfx = SfxData {
+            note: self.note % 12,
+            octave: self.octave.min(7),
+            speed: self.speed.clamp(-4, 3),
+            pitch16x: self.pitch16x,
+            ..SfxData::default()
+        };
+        let peak = self.volume.min(15) as f32;
+        let attack = self.attack as usize;
+        let sustain = attack + self.sustain as usize;
+        let decay = self.decay.max(1) as usize;
+        for (t, tick) in sfx.ticks.iter_mut().enumerate() {
+            let level = if t < attack {
+                peak * (t + 1) as f32 / (attack + 1) as f32
+            } else if t < sustain {
+                peak
+            } else {
+                peak * (1.0 - (t - sustain) as f32 / decay as f32)
+            };
+            tick.volume = (level.max(0.0) + 0.5) as u8;
+
+            let mut pitch = self.start_pitch + self.slide * t as f32;
+            if self.vibrato_depth > 0.0 && self.vibrato_period > 0 {
+                let phase = t as f32 / self.vibrato_period as f32;
+                pitch += self.vibrato_depth * (phase * 2.0 * std::f32::consts::PI).sin();
+            }
+            tick.pitch = (pitch.round() as i32).clamp(-8, 7) as i8;
+
+            if self.arpeggio > 0 && t >= self.arpeggio_tick as usize {
+                tick.arpeggio = self.arpeggio & 0x0f;
+            }
+        }
+        if self.repeat && self.sustain > 0 {
+            sfx.volume_loop = SfxLoop {
+                start: self.attack.min(15),
+                size: self.sustain.min(15),
+            };
+        }
+
+        let waveform = match self.wave {
+            WaveKind::Square(duty) => Waveform::square(duty),
+            WaveKind::Saw => Waveform::saw(),
+            WaveKind::Triangle => Waveform::triangle(),
+            WaveKind::Sine => Waveform::sine(),
+            WaveKind::Noise => Waveform::noise(&mut Rng::new(seed)),
+            WaveKind::Harmonics(amplitudes) => Waveform::harmonics(&amplitudes),
+        };
+        GeneratedSfx { sfx, waveform }
+    }
+}
+
+/// A complete sound effect and the waveform it plays.
+#[derive(Clone, PartialEq, Debug)]
+pub struct GeneratedSfx {
+    pub sfx: SfxData,
+    pub waveform: Waveform,
+}
+
+impl GeneratedSfx {
+    /// Write the waveform to `wave_id` and the effect, pointed at that
+    /// waveform, to `sfx_id`.
+    pub fn write(&mut self, sfx_id: usize, wave_id: usize) -> Result<(), Tic80Error> {
+        self.waveform.write(wave_id)?;
+        self.sfx.wave(wave_id as u8);
+        self.sfx.write(sfx_id)
+    }
+}
+
+/// Seedable sound effect generator, the same seed gives the same sounds.
+pub struct SfxGenerator {
+    rng: Rng,
+}
+
+impl SfxGenerator {
+    pub fn new(seed: u32) -> Self {
+        Self {
+            rng: Rng::new(seed),
+        }
+    }
+
+    /// Random parameters in the range of a preset.
+    pub fn params(&mut self, preset: SfxPreset) -> SfxParams {
+        let rng = &mut self.rng;
+        let base = SfxParams {
+            wave: WaveKind::Square(0.5),
+            note: rng.range(0, 12) as u8,
+            octave: 4,
+            speed: 0,
+            attack: 0,
+            sustain: 2,
+            decay: 8,
+            volume: 15,
+            start_pitch: 0.0,
+            slide: 0.0,
+            pitch16x: false,
+            vibrato_depth: 0.0,
+            vibrato_period: 0,
+            arpeggio: 0,
+            arpeggio_tick: 0,
+            repeat: false,
+        };
+        match preset {
+            SfxPreset::Pickup => SfxParams {
+                wave: WaveKind::Square([0.5, 0.25][rng.range(0, 2) as usize]),
+                octave: rng.range(5, 7) as u8,
+                sustain: rng.range(2, 5) as u8,
+                decay: rng.range(6, 12) as u8,
+                arpeggio: [4, 5, 7, 12][rng.range(0, 4) as usize],
+                arpeggio_tick: rng.range(2, 5) as u8,
+                ..base
+            },
+            SfxPreset::Hurt => SfxParams {
+                wave: if rng.chance(0.5) {
+                    WaveKind::Saw
+                } else {
+                    WaveKind::Noise
+                },
+                octave: rng.range(2, 4) as u8,
+                sustain: rng.range(1, 3) as u8,
+                decay: rng.range(5, 10) as u8,
+                start_pitch: rng.range_f32(2.0, 7.0),
+                slide: -rng.range_f32(0.8, 1.6),
+                ..base
+            },
+            SfxPreset::Explosion => SfxParams {
+                wave: WaveKind::Noise,
+                octave: rng.range(1, 3) as u8,
+                speed: -rng.range(0, 3) as i8,
+                sustain: rng.range(2, 6) as u8,
+                decay: rng.range(14, 24) as u8,
+                start_pitch: rng.range_f32(0.0, 4.0),
+                slide: -rng.range_f32(0.1, 0.4),
+                ..base
+            },
+            SfxPreset::Laser => SfxParams {
+                wave: if rng.chance(0.5) {
+                    WaveKind::Saw
+                } else {
+                    WaveKind::Square(rng.range_f32(0.1, 0.5))
+                },
+                octave: rng.range(5, 7) as u8,
+                sustain: rng.range(1, 4) as u8,
+                decay: rng.range(4, 10) as u8,
+                start_pitch: 7.0,
+                slide: -rng.range_f32(0.5, 1.5),
+                pitch16x: rng.chance(0.5),
+                ..base
+            },
+            SfxPreset::Jump => SfxParams {
+                wave: WaveKind::Square(rng.range_f32(0.25, 0.5)),
+                octave: rng.range(3, 5) as u8,
+                sustain: rng.range(2, 5) as u8,
+                decay: rng.range(6, 12) as u8,
+                start_pitch: -6.0,
+                slide: rng.range_f32(0.6, 1.2),
+                ..base
+            },
+            SfxPreset::Magic => SfxParams {
+                wave: WaveKind::Harmonics([
+                    1.0,
+                    rng.range_f32(0.0, 0.6),
+                    rng.range_f32(0.0, 0.4),
+                    rng.range_f32(0.0, 0.3),
+                ]),
+                octave: rng.range(4, 7) as u8,
+                attack: rng.range(2, 6) as u8,
+                sustain: rng.range(6, 12) as u8,
+                decay: rng.range(8, 14) as u8,
+                volume: 12,
+                vibrato_depth: rng.range_f32(1.0, 3.0),
+                vibrato_period: rng.range(3, 8) as u8,
+                arpeggio: [3, 4, 7][rng.range(0, 3) as usize],
+                arpeggio_tick: rng.range(4, 10) as u8,
+                repeat: rng.chance(0.5),
+                ..base
+            },
+        }
+    }
+
+    /// Generate a sound from a preset.
+    pub fn generate(&mut self, preset: SfxPreset) -> GeneratedSfx {
+        let params = self.params(preset);
+        params.render(self.rng.next_u32())
+    }
+
+    /// Generate a sound from a random preset.
+    pub fn random(&mut self) -> GeneratedSfx {
+        let preset = SfxPreset::ALL[self.rng.range(0, SfxPreset::ALL.len() as i32) as usize];
+        self.generate(preset)
+    }
+
+    /// Randomly nudge the parameters, `amount` 0.0..=1.0.
+    pub fn mutate(&mut self, params: &SfxParams, amount: f32) -> SfxParams {
+        let rng = &mut self.rng;
+        let mut nudge = |value: u8, range: i32| -> u8 {
+            let delta = (rng.range(-range, range + 1) as f32 * amount).round() as i32;
+            (value as i32 + delta).clamp(0, SFX_TICKS as i32) as u8
+        };
+        let mut params = params.clone();
+        params.attack = nudge(params.attack, 2);
+        params.sustain = nudge(params.sustain, 3);
+        params.decay = nudge(params.decay, 4);
+        params.start_pitch += self.rng.range_f32(-2.0, 2.0) * amount;
+        params.slide += self.rng.range_f32(-0.3, 0.3) * amount;
+        params.vibrato_depth =
+            (params.vibrato_depth + self.rng.range_f32(-1.0, 1.0) * amount).max(0.0);
+        params
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_sounds() {
+        let mut a = SfxGenerator::new(42);
+        let mut b = SfxGenerator::new(42);
+        for preset in SfxPreset::ALL {
+            assert_eq!(a.generate(preset), b.generate(preset));
+        }
+        assert_eq!(a.random(), b.random());
+    }
+
+    #[test]
+    fn volume_envelope_rises_holds_and_decays() {
+        let params = SfxParams {
+            attack: 2,
+            sustain: 3,
+            decay: 4,
+            volume: 12,
+            ..SfxGenerator::new(1).params(SfxPreset::Pickup)
+        };
+        let sfx = params.render(0).sfx;
+        let volumes: Vec<u8> = sfx.ticks[..10].iter().map(|t| t.volume).collect();
+        assert_eq!(volumes, [4, 8, 12, 12, 12, 12, 9, 6, 3, 0]);
+    }
+
+    #[test]
+    fn pitch_is_clamped_to_the_envelope_range() {
+        let params = SfxParams {
+            start_pitch: 7.0,
+            slide: -2.0,
+            vibrato_depth: 0.0,
+            ..SfxGenerator::new(1).params(SfxPreset::Laser)
+        };
+        let sfx = params.render(0).sfx;
+        assert_eq!(sfx.ticks[0].pitch, 7);
+        assert_eq!(sfx.ticks[1].pitch, 5);
+        assert!(sfx.ticks[10..].iter().all(|t| t.pitch == -8));
+    }
+
+    #[test]
+    fn repeat_loops_the_sustain() {
+        let params = SfxParams {
+            attack: 3,
+            sustain: 5,
+            repeat: true,
+            ..SfxGenerator::new(1).params(SfxPreset::Magic)
+        };
+        assert_eq!(params.render(0).sfx.volume_loop, SfxLoop { start: 3, size: 5 });
+    }
+
+    #[test]
+    fn mutate_keeps_times_in_range() {
+        let mut generator = SfxGenerator::new(3);
+        let mut params = generator.params(SfxPreset::Explosion);
+        for _ in 0..50 {
+            params = generator.mutate(&params, 1.0);
+            assert!(params.attack as usize <= SFX_TICKS);
+            assert!(params.vibrato_depth >= 0.0);
+        }
+    }
+}
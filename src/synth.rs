@@ -0,0 +1,387 @@
+use std::f32::consts::PI;
+
+use heapless::Vec as Vector;
+
+use crate::tic80::*;
+use crate::tic80_error::Tic80Error;
+use crate::waveform::Waveform;
+
+pub const CHANNELS: usize = 4;
+/// Bytes per channel in `SOUND_REGISTERS`: 12 bit frequency and 4 bit volume,
+/// followed by 32 packed 4 bit waveform samples.
+const REGISTER_SIZE: usize = 18;
+const MAX_FREQUENCY: f32 = 4095.0;
+const MAX_VOLUME: u8 = 15;
+
+/// Frequency in Hz of a note (0 = C .. 11 = B) in an octave, TIC-80 octave 4 holds A 440.
+pub fn note_freq(note: i32, octave: i32) -> f32 {
+    midi_freq((octave + 1) * 12 + note)
+}
+
+/// Frequency in Hz of a MIDI note number.
+pub fn midi_freq(midi: i32) -> f32 {
+    440.0 * semitones(midi as f32 - 69.0)
+}
+
+/// Frequency ratio of an interval in semitones.
+pub fn semitones(n: f32) -> f32 {
+    2.0_f32.powf(n / 12.0)
+}
+
+/// Write one channel's sound register directly.
+pub fn write_register(channel: usize, freq: u16, volume: u8, waveform: &Waveform) {
+    if channel >= CHANNELS {
+        return;
+    }
+    let offset = channel * REGISTER_SIZE;
+    let value = (freq & 0x0fff) | (((volume & 0x0f) as u16) << 12);
+    unsafe {
+        let registers = &mut *SOUND_REGISTERS;
+        registers[offset] = value as u8;
+        registers[offset + 1] = (value >> 8) as u8;
+        registers[offset + 2..offset + REGISTER_SIZE].copy_from_slice(&waveform.to_bytes());
+    }
+}
+
+/// Volume envelope, times are in frames and `sustain` is a level 0..15.
+#[derive(Clone, Copy, Debug)]
+pub struct Envelope {
+    pub attack: u16,
+    pub decay: u16,
+    pub sustain: u8,
+    pub release: u16,
+}
+
+impl Default for Envelope {
+    fn default() -> Self {
+        Self {
+            attack: 0,
+            decay: 0,
+            sustain: MAX_VOLUME,
+            release: 0,
+        }
+    }
+}
+
+impl Envelope {
+    pub fn new(attack: u16, decay: u16, sustain: u8, release: u16) -> Self {
+        Self {
+            attack,
+            decay,
+            sustain: sustain.min(MAX_VOLUME),
+            release,
+        }
+    }
+
+    /// Level 0.0..=1.0 while the note is held, `age` frames after note on.
+    fn held(&self, age: u32) -> f32 {
+        let attack = self.attack as u32;
+        let decay = self.decay as u32;
+        let sustain = self.sustain as f32 / MAX_VOLUME as f32;
+        if age < attack {
+            age as f32 / attack as f32
+        } else if age < attack + decay {
+            let t = (age - attack) as f32 / decay as f32;
+            1.0 + (sustain - 1.0) * t
+        } else {
+            sustain
+        }
+    }
+
+    /// Level `age` frames after note off, starting from `from`.
+    fn released(&self, from: f32, age: u32) -> f32 {
+        if age >= self.release as u32 {
+            0.0
+        } else {
+            from * (1.0 - age as f32 / self.release as f32)
+        }
+    }
+}
+
+/// Periodic pitch wobble, `depth` in semitones and `period` in frames.
+#[derive(Clone, Copy, Debug)]
+pub struct Vibrato {
+    pub depth: f32,
+    pub period: u16,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Slide {
+    target: f32,
+    frames: u16,
+}
+
+/// One synth channel.
+#[derive(Clone, Debug)]
+pub struct Voice {
+    waveform: Waveform,
+    freq: f32,
+    volume: u8,
+    envelope: Envelope,
+    vibrato: Option<Vibrato>,
+    arpeggio: Vector<i8, 8>,
+    arpeggio_speed: u8,
+    slide: Option<Slide>,
+    gate: bool,
+    active: bool,
+    age: u32,
+    release_from: f32,
+    release_age: u32,
+}
+
+impl Default for Voice {
+    fn default() -> Self {
+        Self {
+            waveform: Waveform::default(),
+            freq: 0.0,
+            volume: MAX_VOLUME,
+            envelope: Envelope::default(),
+            vibrato: None,
+            arpeggio: Vector::new(),
+            arpeggio_speed: 1,
+            slide: None,
+            gate: false,
+            active: false,
+            age: 0,
+            release_from: 0.0,
+            release_age: 0,
+        }
+    }
+}
+
+impl Voice {
+    /// Use waveform `id` from `WAVEFORMS`.
+    pub fn wave(&mut self, id: usize) -> Result<&mut Self, Tic80Error> {
+        self.waveform = Waveform::read(id)?;
+        Ok(self)
+    }
+
+    /// Use a waveform built in code.
+    pub fn waveform(&mut self, waveform: Waveform) -> &mut Self {
+        self.waveform = waveform;
+        self
+    }
+
+    pub fn envelope(&mut self, envelope: Envelope) -> &mut Self {
+        self.envelope = envelope;
+        self
+    }
+
+    pub fn vibrato(&mut self, vibrato: Option<Vibrato>) -> &mut Self {
+        self.vibrato = vibrato;
+        self
+    }
+
+    /// Cycle through semitone offsets, `speed` frames per step. Only the
+    /// first 8 offsets are used. An empty slice turns the arpeggio off.
+    pub fn arpeggio(&mut self, offsets: &[i8], speed: u8) -> &mut Self {
+        self.arpeggio.clear();
+        let len = offsets.len().min(self.arpeggio.capacity());
+        // Can't fail, the slice is cut to the capacity.
+        let _ = self.arpeggio.extend_from_slice(&offsets[..len]);
+        self.arpeggio_speed = speed.max(1);
+        self
+    }
+
+    /// Start a note at `freq` Hz with peak `volume` 0..15.
+    pub fn note_on(&mut self, freq: f32, volume: u8) -> &mut Self {
+        self.freq = freq;
+        self.volume = volume.min(MAX_VOLUME);
+        self.slide = None;
+        self.gate = true;
+        self.active = true;
+        self.age = 0;
+        self
+    }
+
+    /// Release the note, it fades out over the envelope's release time.
+    pub fn note_off(&mut self) -> &mut Self {
+        if self.gate {
+            self.release_from = self.envelope.held(self.age);
+            self.release_age = 0;
+            self.gate = false;
+        }
+        self
+    }
+
+    /// Silence the channel immediately.
+    pub fn stop(&mut self) -> &mut Self {
+        self.gate = false;
+        self.active = false;
+        self
+    }
+
+    /// Sweep the pitch to `freq` Hz over `frames` frames.
+    pub fn slide_to(&mut self, freq: f32, frames: u16) -> &mut Self {
+        if frames == 0 {
+            self.freq = freq;
+            self.slide = None;
+        } else {
+            self.slide = Some(Slide {
+                target: freq,
+                frames,
+            });
+        }
+        self
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Advance one frame, returning the frequency and volume to output.
+    fn step(&mut self) -> (u16, u8) {
+        if !self.active {
+            return (0, 0);
+        }
+
+        if let Some(slide) = self.slide.as_mut() {
+            if self.freq <= 0.0 {
+                self.freq = slide.target;
+            }
+            // Slide in pitch rather than in Hz so sweeps sound even.
+            self.freq *= (slide.target / self.freq).powf(1.0 / slide.frames as f32);
+            slide.frames -= 1;
+            if slide.frames == 0 {
+                self.freq = slide.target;
+                self.slide = None;
+            }
+        }
+
+        let mut freq = self.freq;
+        if !self.arpeggio.is_empty() {
+            let step = self.age as usize / self.arpeggio_speed as usize;
+            freq *= semitones(self.arpeggio[step % self.arpeggio.len()] as f32);
+        }
+        if let Some(vibrato) = self.vibrato.filter(|v| v.period > 0) {
+            let phase = (self.age % vibrato.period as u32) as f32 / vibrato.period as f32;
+            freq *= semitones(vibrato.depth * (phase * 2.0 * PI).sin());
+        }
+
+        let level = if self.gate {
+            self.envelope.held(self.age)
+        } else {
+            let level = self.envelope.released(self.release_from, self.release_age);
+            self.release_age += 1;
+            if level <= 0.0 {
+                self.active = false;
+            }
+            level
+        };
+        self.age += 1;
+
+        let volume = (level * self.volume as f32 + 0.5) as u8;
+        (
+            freq.clamp(0.0, MAX_FREQUENCY) as u16,
+            volume.min(MAX_VOLUME),
+        )
+    }
+}
+
+/// Drives the four channels through `SOUND_REGISTERS`. TIC-80 clears the
+/// registers every frame, so [`Synth::update`] must be called from `TIC`.
+/// Channels also used by `sfx` or `music` will be overwritten by them.
+#[derive(Default)]
+pub struct Synth {
+    voices: [Voice; CHANNELS],
+}
+
+impl Synth {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The voice of `channel` 0..3.
+    pub fn channel(&mut self, channel: usize) -> Result<&mut Voice, Tic80Error> {
+        self.voices.get_mut(channel).ok_or(Tic80Error::OutOfRange {
+            index: channel,
+            len: CHANNELS,
+        })
+    }
+
+    /// Advance every active voice by one frame and write the registers.
+    pub fn update(&mut self) {
+        for (channel, voice) in self.voices.iter_mut().enumerate() {
+            if voice.active {
+                let (freq, volume) = voice.step();
+                write_register(channel, freq, volume, &voice.waveform);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn channels_past_the_last_are_errors() {
+        let mut synth = Synth::new();
+        assert!(synth.channel(CHANNELS - 1).is_ok());
+        assert!(matches!(
+            synth.channel(CHANNELS),
+            Err(Tic80Error::OutOfRange { index: 4, len: 4 })
+        ));
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 0.01
+    }
+
+    #[test]
+    fn note_frequencies() {
+        assert!(close(note_freq(9, 4), 440.0));
+        assert!(close(note_freq(9, 5), 880.0));
+        assert!(close(note_freq(0, 4), 261.63));
+        assert!(close(midi_freq(57), 220.0));
+    }
+
+    #[test]
+    fn envelope_attack_decay_sustain_release() {
+        let envelope = Envelope::new(4, 4, 6, 10);
+        assert!(close(envelope.held(0), 0.0));
+        assert!(close(envelope.held(2), 0.5));
+        assert!(close(envelope.held(4), 1.0));
+        assert!(close(envelope.held(6), 0.7));
+        assert!(close(envelope.held(100), 0.4));
+        assert!(close(envelope.released(0.4, 5), 0.2));
+        assert!(close(envelope.released(0.4, 10), 0.0));
+    }
+
+    #[test]
+    fn voice_fades_out_after_note_off() {
+        let mut voice = Voice::default();
+        voice.envelope(Envelope::new(0, 0, 15, 2)).note_on(440.0, 15);
+        assert_eq!(voice.step(), (440, 15));
+        voice.note_off();
+        assert_eq!(voice.step(), (440, 15));
+        assert_eq!(voice.step().1, 8);
+        assert_eq!(voice.step().1, 0);
+        assert!(!voice.is_active());
+        assert_eq!(voice.step(), (0, 0));
+    }
+
+    #[test]
+    fn arpeggio_steps_through_offsets() {
+        let mut voice = Voice::default();
+        voice.arpeggio(&[0, 12], 2).note_on(220.0, 15);
+        let freqs: Vec<u16> = (0..6).map(|_| voice.step().0).collect();
+        assert_eq!(freqs, [220, 220, 440, 440, 220, 220]);
+    }
+
+    #[test]
+    fn arpeggio_keeps_the_first_eight_offsets() {
+        let mut voice = Voice::default();
+        voice.arpeggio(&[1; 12], 1);
+        assert_eq!(voice.arpeggio.len(), 8);
+    }
+
+    #[test]
+    fn slide_reaches_the_target() {
+        let mut voice = Voice::default();
+        voice.note_on(100.0, 15).slide_to(400.0, 2);
+        assert_eq!(voice.step().0, 200);
+        assert_eq!(voice.step().0, 400);
+        assert_eq!(voice.step().0, 400);
+    }
+}
@@ -0,0 +1,249 @@
+use crate::actions::repeats;
+use crate::tic80::*;
+
+/// Frames a key is held before it repeats, and frames between repeats.
+const REPEAT_HOLD: u32 = 20;
+const REPEAT_PERIOD: u32 = 3;
+/// Frames the caret stays on, then off.
+const BLINK_FRAMES: u32 = 20;
+/// Width of a character with fixed width printing.
+const CHAR_WIDTH: i32 = 6;
+
+/// The characters for the keys from `KEY_MINUS` to `KEY_SPACE`, unshifted
+/// and shifted (US layout).
+const SYMBOLS: &[u8; 12] = b"-=[]\\;'`,./ ";
+const SHIFTED_SYMBOLS: &[u8; 12] = b"_+{}|:\"~<>? ";
+const SHIFTED_DIGITS: &[u8; 10] = b")!@#$%^&*(";
+
+/// The character `code` types, `None` for keys that don't type anything.
+pub fn key_char(code: i32, shift: bool) -> Option<char> {
+    let byte = match code {
+        KEY_A..=KEY_Z => {
+            let base = if shift { b'A' } else { b'a' };
+            base + (code - KEY_A) as u8
+        }
+        KEY_0..=KEY_9 if shift => SHIFTED_DIGITS[(code - KEY_0) as usize],
+        KEY_0..=KEY_9 => b'0' + (code - KEY_0) as u8,
+        KEY_MINUS..=KEY_SPACE if shift => SHIFTED_SYMBOLS[(code - KEY_MINUS) as usize],
+        KEY_MINUS..=KEY_SPACE => SYMBOLS[(code - KEY_MINUS) as usize],
+        _ => return None,
+    };
+    Some(byte as char)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TextInputEvent {
+    /// Return was pressed.
+    Submit,
+    /// Tab was pressed, e.g. to leave the field.
+    Cancel,
+}
+
+/// A single line text field typed on the keyboard. Call
+/// [`TextInput::update`] every frame it has focus.
+#[derive(Clone, Debug)]
+pub struct TextInput {
+    text: String,
+    /// Position of the caret, in characters.
+    cursor: usize,
+    max_len: usize,
+    frame: u32,
+    /// The keys down last frame and the frames they've been down.
+    held: [(u8, u32); 4],
+}
+
+impl TextInput {
+    pub fn new(max_len: usize) -> Self {
+        Self {
+            text: String::new(),
+            cursor: 0,
+            max_len,
+            frame: 0,
+            held: [(0, 0); 4],
+        }
+    }
+
+    /// Start with `text`, cut to the maximum length. Only ASCII is kept,
+    /// like typing, so the caret can count bytes.
+    pub fn with_text(mut self, text: &str) -> Self {
+        self.text = text
+            .chars()
+            .filter(char::is_ascii)
+            .take(self.max_len)
+            .collect();
+        self.cursor = self.text.len();
+        self
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn clear(&mut self) {
+        self.text.clear();
+        self.cursor = 0;
+    }
+
+    /// Handle this frame's keys.
+    pub fn update(&mut self) -> Option<TextInputEvent> {
+        // `KEYBOARD` holds up to four keys that are down this frame.
+        self.update_with(unsafe { *KEYBOARD })
+    }
+
+    /// Handle the keycodes down this frame, as `KEYBOARD` lists them.
+    pub fn update_with(&mut self, keys: [u8; 4]) -> Option<TextInputEvent> {
+        self.frame += 1;
+        let previous = self.held;
+        self.held = keys.map(|code| {
+            let frames = previous
+                .iter()
+                .find(|&&(held, _)| held == code)
+                .map_or(0, |&(_, frames)| frames);
+            (code, frames + 1)
+        });
+        let shift = keys.contains(&(KEY_SHIFT as u8));
+        let mut event = None;
+        for (code, held) in self.held.into_iter().filter(|&(code, _)| code != 0) {
+            if !repeats(held, REPEAT_HOLD, REPEAT_PERIOD) {
+                continue;
+            }
+            let code = code as i32;
+            // Typing shows the caret right away.
+            self.frame = 0;
+            match code {
+                KEY_RETURN => event = Some(TextInputEvent::Submit),
+                KEY_TAB => event = Some(TextInputEvent::Cancel),
+                KEY_BACKSPACE if self.cursor > 0 => {
+                    self.cursor -= 1;
+                    self.text.remove(self.cursor);
+                }
+                KEY_DELETE if self.cursor < self.text.len() => {
+                    self.text.remove(self.cursor);
+                }
+                KEY_LEFT => self.cursor = self.cursor.saturating_sub(1),
+                KEY_RIGHT => self.cursor = (self.cursor + 1).min(self.text.len()),
+                KEY_HOME => self.cursor = 0,
+                KEY_END => self.cursor = self.text.len(),
+                _ => {
+                    if let Some(c) = key_char(code, shift) {
+                        self.insert(c);
+                    }
+                }
+            }
+        }
+        event
+    }
+
+    /// Type `c` at the caret, ignored at the maximum length.
+    pub fn insert(&mut self, c: char) {
+        if self.text.len() < self.max_len && c.is_ascii() {
+            self.text.insert(self.cursor, c);
+            self.cursor += 1;
+        }
+    }
+
+    /// Draw the text at `(x, y)` with a blinking caret.
+    pub fn draw(&self, x: i32, y: i32, color: i8) {
+        Print::default()
+            .x(x)
+            .y(y)
+            .color(color)
+            .fixed(true)
+            .print(format!("{}\0", self.text));
+        if (self.frame / BLINK_FRAMES).is_multiple_of(2) {
+            Print::default()
+                .x(x + self.cursor as i32 * CHAR_WIDTH)
+                .y(y + 1)
+                .color(color)
+                .print("_\0");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tap(input: &mut TextInput, code: i32) -> Option<TextInputEvent> {
+        let event = input.update_with([code as u8, 0, 0, 0]);
+        input.update_with([0; 4]);
+        event
+    }
+
+    #[test]
+    fn maps_keys_to_characters() {
+        assert_eq!(key_char(KEY_A, false), Some('a'));
+        assert_eq!(key_char(KEY_Z, true), Some('Z'));
+        assert_eq!(key_char(KEY_0, false), Some('0'));
+        assert_eq!(key_char(KEY_1, true), Some('!'));
+        assert_eq!(key_char(KEY_MINUS, true), Some('_'));
+        assert_eq!(key_char(KEY_SLASH, false), Some('/'));
+        assert_eq!(key_char(KEY_SPACE, true), Some(' '));
+        assert_eq!(key_char(KEY_RETURN, false), None);
+        assert_eq!(key_char(KEY_SHIFT, false), None);
+    }
+
+    #[test]
+    fn non_ascii_start_text_is_dropped() {
+        let mut input = TextInput::new(4).with_text("Zoë the wise");
+        assert_eq!(input.text(), "Zo t");
+        tap(&mut input, KEY_BACKSPACE);
+        tap(&mut input, KEY_HOME);
+        tap(&mut input, KEY_DELETE);
+        assert_eq!(input.text(), "o ");
+    }
+
+    #[test]
+    fn edits_at_the_caret() {
+        let mut input = TextInput::new(8);
+        for code in [KEY_A, KEY_B, KEY_C] {
+            tap(&mut input, code);
+        }
+        tap(&mut input, KEY_LEFT);
+        tap(&mut input, KEY_BACKSPACE);
+        assert_eq!(input.text(), "ac");
+        tap(&mut input, KEY_HOME);
+        input.update_with([KEY_SHIFT as u8, KEY_X as u8, 0, 0]);
+        input.update_with([0; 4]);
+        assert_eq!(input.text(), "Xac");
+        tap(&mut input, KEY_END);
+        tap(&mut input, KEY_DELETE);
+        tap(&mut input, KEY_RIGHT);
+        tap(&mut input, KEY_D);
+        assert_eq!(input.text(), "Xacd");
+    }
+
+    #[test]
+    fn stops_at_the_maximum_length() {
+        let mut input = TextInput::new(2);
+        for _ in 0..3 {
+            tap(&mut input, KEY_Q);
+        }
+        input.insert('é');
+        assert_eq!(input.text(), "qq");
+        input.clear();
+        input.insert('é');
+        assert_eq!(input.text(), "");
+    }
+
+    #[test]
+    fn return_and_tab_are_events() {
+        let mut input = TextInput::new(8);
+        assert_eq!(tap(&mut input, KEY_RETURN), Some(TextInputEvent::Submit));
+        assert_eq!(tap(&mut input, KEY_TAB), Some(TextInputEvent::Cancel));
+        assert_eq!(tap(&mut input, KEY_A), None);
+    }
+
+    #[test]
+    fn held_keys_repeat_from_the_keyboard_state() {
+        let mut input = TextInput::new(64);
+        let a = [KEY_A as u8, 0, 0, 0];
+        for _ in 0..REPEAT_HOLD + 1 + REPEAT_PERIOD {
+            input.update_with(a);
+        }
+        assert_eq!(input.text(), "aaa");
+        input.update_with([0; 4]);
+        input.update_with(a);
+        assert_eq!(input.text(), "aaaa");
+    }
+}
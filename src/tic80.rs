@@ -3,9 +3,10 @@
 use derive_builder::Builder;
 use heapless::Vec as Vector;
 
+use std::cell::RefCell;
 use std::ffi::{CStr, CString, NulError};
 use std::intrinsics::transmute;
-use std::ops::{Add, Deref};
+use std::ops::{Add, BitAnd, BitOr, BitOrAssign, Deref, Not, Sub};
 use std::os::raw::c_char;
 
 use crate::tic80_error::Tic80Error;
@@ -17,6 +18,7 @@ pub const HEIGHT: u32 = 136;
 
 // VRAM bank 0 screen area
 pub static mut FRAMEBUFFER_PTR: *mut [u8; 16320] = 0x00 as *mut [u8; 16320];
+pub static mut PALETTE: *mut [u8; 48] = 0x3FC0 as *mut [u8; 48];
 pub static mut TILES: *mut [u8; 8192] = 0x4000 as *mut [u8; 8192];
 pub static mut SPRITES: *mut [u8; 8192] = 0x6000 as *mut [u8; 8192];
 pub static mut MAP: *mut [u8; 32640] = 0x8000 as *mut [u8; 32640];
@@ -73,9 +75,14 @@ impl Btnp {
 
     /// [btnp](https://github.com/nesbox/TIC-80/wiki/btnp)
     /// Returns true if the given button was pressed in the previous frame.
+    /// Repeats follow the written input while a replay plays.
     pub fn btnp(&self) -> bool {
         // Okay to unwrap because of default field values
         let args = self.build().unwrap();
+        let counted = with_hold_counts(|holds| holds.btnp(args.id, args.hold, args.period));
+        if let Some(pressed) = counted {
+            return pressed;
+        }
         unsafe { extern_btnp(args.id, args.hold, args.period) > 0 }
     }
 
@@ -84,6 +91,9 @@ impl Btnp {
     pub fn btnp_bits(self) -> i32 {
         // Okay to unwrap because of default field values
         let args = self.build().unwrap();
+        if let Some(bits) = with_hold_counts(HoldCounts::btnp_bits) {
+            return bits;
+        }
         unsafe { extern_btnp(-1, args.hold, args.period) }
     }
 }
@@ -119,8 +129,9 @@ impl Clip {
         unsafe { extern_clip(-1, -1, -1, -1) }
     }
     /// [clip](https://github.com/nesbox/TIC-80/wiki/clip)
-    /// Sets the clipping region
-    pub fn clip<T>(self) {
+    /// Sets the clipping region, replacing any region from
+    /// `clip::push_clip` until its guard is dropped.
+    pub fn clip(&self) {
         // Okay to unwrap because of default field values
         let args = self.build().unwrap();
         unsafe { extern_clip(args.x, args.y, args.w, args.h) }
@@ -329,6 +340,75 @@ extern "C" {
     ) -> i32;
 }
 
+// Keycodes for key() and keyp().
+pub const KEY_A: i32 = 1;
+pub const KEY_B: i32 = 2;
+pub const KEY_C: i32 = 3;
+pub const KEY_D: i32 = 4;
+pub const KEY_E: i32 = 5;
+pub const KEY_F: i32 = 6;
+pub const KEY_G: i32 = 7;
+pub const KEY_H: i32 = 8;
+pub const KEY_I: i32 = 9;
+pub const KEY_J: i32 = 10;
+pub const KEY_K: i32 = 11;
+pub const KEY_L: i32 = 12;
+pub const KEY_M: i32 = 13;
+pub const KEY_N: i32 = 14;
+pub const KEY_O: i32 = 15;
+pub const KEY_P: i32 = 16;
+pub const KEY_Q: i32 = 17;
+pub const KEY_R: i32 = 18;
+pub const KEY_S: i32 = 19;
+pub const KEY_T: i32 = 20;
+pub const KEY_U: i32 = 21;
+pub const KEY_V: i32 = 22;
+pub const KEY_W: i32 = 23;
+pub const KEY_X: i32 = 24;
+pub const KEY_Y: i32 = 25;
+pub const KEY_Z: i32 = 26;
+pub const KEY_0: i32 = 27;
+pub const KEY_1: i32 = 28;
+pub const KEY_2: i32 = 29;
+pub const KEY_3: i32 = 30;
+pub const KEY_4: i32 = 31;
+pub const KEY_5: i32 = 32;
+pub const KEY_6: i32 = 33;
+pub const KEY_7: i32 = 34;
+pub const KEY_8: i32 = 35;
+pub const KEY_9: i32 = 36;
+pub const KEY_MINUS: i32 = 37;
+pub const KEY_EQUALS: i32 = 38;
+pub const KEY_LEFTBRACKET: i32 = 39;
+pub const KEY_RIGHTBRACKET: i32 = 40;
+pub const KEY_BACKSLASH: i32 = 41;
+pub const KEY_SEMICOLON: i32 = 42;
+pub const KEY_APOSTROPHE: i32 = 43;
+pub const KEY_GRAVE: i32 = 44;
+pub const KEY_COMMA: i32 = 45;
+pub const KEY_PERIOD: i32 = 46;
+pub const KEY_SLASH: i32 = 47;
+pub const KEY_SPACE: i32 = 48;
+pub const KEY_TAB: i32 = 49;
+pub const KEY_RETURN: i32 = 50;
+pub const KEY_BACKSPACE: i32 = 51;
+pub const KEY_DELETE: i32 = 52;
+pub const KEY_INSERT: i32 = 53;
+pub const KEY_PAGEUP: i32 = 54;
+pub const KEY_PAGEDOWN: i32 = 55;
+pub const KEY_HOME: i32 = 56;
+pub const KEY_END: i32 = 57;
+pub const KEY_UP: i32 = 58;
+pub const KEY_DOWN: i32 = 59;
+pub const KEY_LEFT: i32 = 60;
+pub const KEY_RIGHT: i32 = 61;
+pub const KEY_CAPSLOCK: i32 = 62;
+pub const KEY_CTRL: i32 = 63;
+pub const KEY_SHIFT: i32 = 64;
+pub const KEY_ALT: i32 = 65;
+/// Number of keycodes, they run from 1 to `KEY_COUNT`.
+pub const KEY_COUNT: i32 = KEY_ALT;
+
 /// [key](https://github.com/nesbox/TIC-80/wiki/key)
 /// Returns `true` if the key denoted by `keycode` is pressed in the current frame.
 pub fn key(keycode: i32) -> bool {
@@ -355,16 +435,28 @@ pub struct KeypArgs {
     period: i32,
 }
 
-impl KeypArgs {
+impl Keyp {
     /// [keyp](https://github.com/nesbox/TIC-80/wiki/keyp)
     /// Returns `true` if the key denoted by `keycode` is pressed in the previous frame.
-    pub fn keyp(self) -> bool {
-        unsafe { extern_keyp(self.id, self.hold, self.period) > 0 }
+    /// Repeats follow the written input while a replay plays.
+    pub fn keyp(&self) -> bool {
+        // Okay to unwrap because of default field values
+        let args = self.build().unwrap();
+        let counted = with_hold_counts(|holds| holds.keyp(args.id, args.hold, args.period));
+        if let Some(pressed) = counted {
+            return pressed;
+        }
+        unsafe { extern_keyp(args.id, args.hold, args.period) > 0 }
     }
     /// [keyp](https://github.com/nesbox/TIC-80/wiki/keyp)
     /// Returns the bits of the pressed keys in the previous frame.
-    pub fn keyp_bit(self) -> i32 {
-        unsafe { extern_keyp(-1, self.hold, self.period) }
+    pub fn keyp_bit(&self) -> i32 {
+        // Okay to unwrap because of default field values
+        let args = self.build().unwrap();
+        if let Some(pressed) = with_hold_counts(|holds| holds.keyp(-1, args.hold, args.period)) {
+            return pressed as i32;
+        }
+        unsafe { extern_keyp(-1, args.hold, args.period) }
     }
 }
 extern "C" {
@@ -372,6 +464,96 @@ extern "C" {
     fn extern_keyp(id: i32, hold: i32, period: i32) -> i32;
 }
 
+/// Frames each button and key has been held, this one included, counted
+/// from the input regions of RAM. TIC-80 counts the `btnp` and `keyp`
+/// repeats from the live input, which writing over the regions doesn't
+/// change, so a replay counts here instead.
+#[derive(Clone, Debug)]
+pub(crate) struct HoldCounts {
+    buttons: [u32; 32],
+    keys: [u32; KEY_COUNT as usize + 1],
+}
+
+impl HoldCounts {
+    pub(crate) fn new() -> Self {
+        Self {
+            buttons: [0; 32],
+            keys: [0; KEY_COUNT as usize + 1],
+        }
+    }
+
+    /// Count one frame of the `gamepads` and `keyboard` regions.
+    pub(crate) fn advance(&mut self, gamepads: [u8; 4], keyboard: [u8; 4]) {
+        let bits = u32::from_le_bytes(gamepads);
+        for (id, held) in self.buttons.iter_mut().enumerate() {
+            *held = if bits & 1 << id != 0 { *held + 1 } else { 0 };
+        }
+        // Keycode 0 is an empty slot.
+        for (code, held) in self.keys.iter_mut().enumerate().skip(1) {
+            *held = if keyboard.contains(&(code as u8)) { *held + 1 } else { 0 };
+        }
+    }
+
+    /// Like [`Btnp::btnp`], any button for a negative `id`.
+    pub(crate) fn btnp(&self, id: i32, hold: i32, period: i32) -> bool {
+        match usize::try_from(id) {
+            Ok(id) => self.buttons.get(id).is_some_and(|&held| repeats(held, hold, period)),
+            Err(_) => self.btnp_bits() != 0,
+        }
+    }
+
+    /// Like [`Btnp::btnp_bits`], TIC-80 ignores `hold` and `period` here.
+    pub(crate) fn btnp_bits(&self) -> i32 {
+        self.buttons
+            .iter()
+            .enumerate()
+            .filter(|&(_, &held)| held == 1)
+            .fold(0, |bits, (id, _)| bits | 1 << id)
+    }
+
+    /// Like [`Keyp::keyp`], any key for a negative `id`.
+    pub(crate) fn keyp(&self, id: i32, hold: i32, period: i32) -> bool {
+        match usize::try_from(id) {
+            Ok(id) => self.keys.get(id).is_some_and(|&held| repeats(held, hold, period)),
+            Err(_) => self.keys.iter().any(|&held| repeats(held, hold, period)),
+        }
+    }
+}
+
+/// Whether an input held for `held` frames reads as pressed with `hold`
+/// and `period`, the way TIC-80 reads its own counts. A negative `hold` or
+/// `period` only reads the first frame.
+fn repeats(held: u32, hold: i32, period: i32) -> bool {
+    match (u32::try_from(hold), u32::try_from(period)) {
+        _ if held == 0 => false,
+        (Ok(hold), Ok(period)) if held >= hold => period == 0 || held.is_multiple_of(period),
+        _ => held == 1,
+    }
+}
+
+thread_local! {
+    static HOLD_COUNTS: RefCell<Option<HoldCounts>> = const { RefCell::new(None) };
+}
+
+/// Read `btnp` and `keyp` from counts of the input regions, fed by
+/// [`advance_hold_counts`], or from TIC-80's own counts again with `false`.
+pub(crate) fn count_holds(enabled: bool) {
+    HOLD_COUNTS.with(|counts| *counts.borrow_mut() = enabled.then(HoldCounts::new));
+}
+
+/// Count this frame's written input, see [`count_holds`].
+pub(crate) fn advance_hold_counts(gamepads: [u8; 4], keyboard: [u8; 4]) {
+    HOLD_COUNTS.with(|counts| {
+        if let Some(counts) = counts.borrow_mut().as_mut() {
+            counts.advance(gamepads, keyboard);
+        }
+    });
+}
+
+fn with_hold_counts<T>(f: impl FnOnce(&HoldCounts) -> T) -> Option<T> {
+    HOLD_COUNTS.with(|counts| counts.borrow().as_ref().map(f))
+}
+
 /// [line](https://github.com/nesbox/TIC-80/wiki/line)
 /// Draws a straight line from point (x0,y0) to point (x1,y1) in the specified color.
 pub fn line(x0: f32, y0: f32, x1: f32, y1: f32, color: i8) {
@@ -502,15 +684,15 @@ extern "C" {
 /// [mouse](https://github.com/nesbox/TIC-80/wiki/mouse)
 /// Pass to the [`mouse`] function to populate.
 #[repr(C)]
-#[derive(Default)]
+#[derive(Clone, Copy, Default, Debug)]
 pub struct MouseData {
-    x: i16,
-    y: i16,
-    scrollx: i8,
-    scrolly: i8,
-    left: bool,
-    middle: bool,
-    right: bool,
+    pub x: i16,
+    pub y: i16,
+    pub scrollx: i8,
+    pub scrolly: i8,
+    pub left: bool,
+    pub middle: bool,
+    pub right: bool,
 }
 
 /// [mouse](https://github.com/nesbox/TIC-80/wiki/mouse)
@@ -523,23 +705,48 @@ extern "C" {
     fn extern_mouse(data: *mut MouseData);
 }
 
-/// [music](https://github.com/nesbox/TIC-80/wiki/music)
-/// Starts playing a track created in the Music Editor.
-pub fn music<T, U>(track: T, frame: T, row: T, loop_music: U, sustain: U, tempo: T, speed: T)
-where
-    T: Into<Option<i32>>,
-    U: Into<Option<bool>>,
-{
-    unsafe {
-        extern_music(
-            track.into().unwrap_or(-1),
-            frame.into().unwrap_or(-1),
-            row.into().unwrap_or(-1),
-            loop_music.into().unwrap_or(true),
-            sustain.into().unwrap_or(false),
-            tempo.into().unwrap_or(-1),
-            speed.into().unwrap_or(-1),
-        )
+#[derive(Builder)]
+#[builder(name = "Music", build_fn(private))]
+pub struct MusicArgs {
+    #[builder(setter(into), default = "-1")]
+    pub track: i32,
+    #[builder(setter(into), default = "-1")]
+    pub frame: i32,
+    #[builder(setter(into), default = "-1")]
+    pub row: i32,
+    #[builder(setter(into), default = "true")]
+    pub looping: bool,
+    #[builder(setter(into), default = "false")]
+    pub sustain: bool,
+    #[builder(setter(into), default = "-1")]
+    pub tempo: i32,
+    #[builder(setter(into), default = "-1")]
+    pub speed: i32,
+}
+
+impl Music {
+    /// [music](https://github.com/nesbox/TIC-80/wiki/music)
+    /// Starts playing a track created in the Music Editor.
+    pub fn play(&self) {
+        // Okay to unwrap because of default field values
+        let args = self.build().unwrap();
+        unsafe {
+            extern_music(
+                args.track,
+                args.frame,
+                args.row,
+                args.looping,
+                args.sustain,
+                args.tempo,
+                args.speed,
+            )
+        }
+    }
+
+    /// [music](https://github.com/nesbox/TIC-80/wiki/music)
+    /// Stops playing music.
+    pub fn stop() {
+        unsafe { extern_music(-1, -1, -1, false, false, -1, -1) }
     }
 }
 extern "C" {
@@ -627,8 +834,8 @@ pub fn pmem_get(index: i32) -> u32 {
 }
 /// [pmem](https://github.com/nesbox/TIC-80/wiki/pmem)
 /// Save new value to persistent memory, retrieve prior value.
-pub fn pmem_set(index: i32, value: i64) -> u32 {
-    unsafe { extern_pmem(index, value) }
+pub fn pmem_set(index: i32, value: u32) -> u32 {
+    unsafe { extern_pmem(index, value.into()) }
 }
 extern "C" {
     #[link_name = "pmem"]
@@ -765,7 +972,7 @@ extern "C" {
 
 #[derive(Builder)]
 #[builder(name = "Sfx", build_fn(private))]
-struct SfxArgs {
+pub struct SfxArgs {
     #[builder(setter(into), default = "-1")]
     note: i32,
     #[builder(setter(into), default = "-1")]
@@ -878,11 +1085,108 @@ extern "C" {
     );
 }
 
+/// Cart sections for [`sync`].
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, Debug)]
+pub struct SyncMask(u8);
+
+impl SyncMask {
+    pub const TILES: SyncMask = SyncMask(1);
+    pub const SPRITES: SyncMask = SyncMask(1 << 1);
+    pub const MAP: SyncMask = SyncMask(1 << 2);
+    pub const SFX: SyncMask = SyncMask(1 << 3);
+    pub const MUSIC: SyncMask = SyncMask(1 << 4);
+    pub const PALETTE: SyncMask = SyncMask(1 << 5);
+    pub const FLAGS: SyncMask = SyncMask(1 << 6);
+    pub const SCREEN: SyncMask = SyncMask(1 << 7);
+    pub const NONE: SyncMask = SyncMask(0);
+    pub const ALL: SyncMask = SyncMask(0xff);
+    /// Every section, in bit order.
+    pub const SECTIONS: [SyncMask; 8] = [
+        Self::TILES,
+        Self::SPRITES,
+        Self::MAP,
+        Self::SFX,
+        Self::MUSIC,
+        Self::PALETTE,
+        Self::FLAGS,
+        Self::SCREEN,
+    ];
+
+    pub const fn from_bits(bits: u8) -> Self {
+        SyncMask(bits)
+    }
+
+    pub const fn bits(self) -> u8 {
+        self.0
+    }
+
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub const fn contains(self, other: SyncMask) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// `self | other`, usable in constants.
+    pub const fn union(self, other: SyncMask) -> SyncMask {
+        SyncMask(self.0 | other.0)
+    }
+
+    pub const fn intersects(self, other: SyncMask) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    /// The single sections in this mask.
+    pub fn sections(self) -> impl Iterator<Item = SyncMask> {
+        Self::SECTIONS
+            .into_iter()
+            .filter(move |&section| self.contains(section))
+    }
+}
+
+impl BitOr for SyncMask {
+    type Output = SyncMask;
+    fn bitor(self, rhs: SyncMask) -> SyncMask {
+        SyncMask(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for SyncMask {
+    fn bitor_assign(&mut self, rhs: SyncMask) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl BitAnd for SyncMask {
+    type Output = SyncMask;
+    fn bitand(self, rhs: SyncMask) -> SyncMask {
+        SyncMask(self.0 & rhs.0)
+    }
+}
+
+impl Sub for SyncMask {
+    type Output = SyncMask;
+    fn sub(self, rhs: SyncMask) -> SyncMask {
+        SyncMask(self.0 & !rhs.0)
+    }
+}
+
+impl Not for SyncMask {
+    type Output = SyncMask;
+    fn not(self) -> SyncMask {
+        SyncMask(!self.0)
+    }
+}
+
 /// [sync](https://github.com/nesbox/TIC-80/wiki/sync)
 /// Save cart data modified during runtime.
-pub fn sync(mask: Option<i32>, bank: Option<i8>, to_cart: bool) {
+/// With `to_cart` false the sections are loaded from `bank` instead.
+/// `None` syncs every section with bank 0.
+pub fn sync(mask: Option<SyncMask>, bank: Option<i8>, to_cart: bool) {
     let to_cart = if to_cart { 1 } else { 0 };
-    unsafe { extern_sync(mask.unwrap_or(-1), bank.unwrap_or(-1), to_cart) }
+    let mask = mask.map_or(-1, |mask| mask.bits() as i32);
+    unsafe { extern_sync(mask, bank.unwrap_or(-1), to_cart) }
 }
 extern "C" {
     #[link_name = "sync"]
@@ -3,10 +3,15 @@ use std::ffi::NulError;
 use std::fmt::Display;
 use std::num::TryFromIntError;
 
+use crate::note::NoteParseError;
+
 #[derive(Debug)]
 pub enum Tic80Error {
     TryFromIntError(TryFromIntError),
     NulCStringError(NulError),
+    OutOfRange { index: usize, len: usize },
+    NoteParseError(NoteParseError),
+    InvalidData(&'static str),
 }
 
 impl Error for Tic80Error {}
@@ -23,11 +28,22 @@ impl From<TryFromIntError> for Tic80Error {
     }
 }
 
+impl From<NoteParseError> for Tic80Error {
+    fn from(e: NoteParseError) -> Self {
+        Tic80Error::NoteParseError(e)
+    }
+}
+
 impl Display for Tic80Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Tic80Error::TryFromIntError(e) => write!(f, "{}", e),
             Tic80Error::NulCStringError(e) => write!(f, "{}", e),
+            Tic80Error::OutOfRange { index, len } => {
+                write!(f, "index {} out of range for length {}", index, len)
+            }
+            Tic80Error::NoteParseError(e) => write!(f, "{}", e),
+            Tic80Error::InvalidData(what) => write!(f, "invalid {}", what),
         }
     }
 }
@@ -0,0 +1,204 @@
+use std::f32::consts::PI;
+
+use crate::rng::Rng;
+use crate::tic80::*;
+use crate::tic80_error::Tic80Error;
+
+pub const WAVEFORM_COUNT: usize = 16;
+pub const WAVEFORM_SAMPLES: usize = 32;
+const WAVEFORM_SIZE: usize = WAVEFORM_SAMPLES / 2;
+const MAX_SAMPLE: u8 = 15;
+
+/// 32 samples of 4 bits each, the format of `WAVEFORMS` and the sound registers.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Waveform {
+    pub samples: [u8; WAVEFORM_SAMPLES],
+}
+
+impl Default for Waveform {
+    fn default() -> Self {
+        Self::square(0.5)
+    }
+}
+
+impl Waveform {
+    /// Build from a function of the phase `0.0..1.0` returning `-1.0..=1.0`.
+    pub fn from_fn<F: Fn(f32) -> f32>(f: F) -> Self {
+        let mut samples = [0; WAVEFORM_SAMPLES];
+        for (i, sample) in samples.iter_mut().enumerate() {
+            let value = f(i as f32 / WAVEFORM_SAMPLES as f32).clamp(-1.0, 1.0);
+            *sample = ((value + 1.0) * 0.5 * MAX_SAMPLE as f32 + 0.5) as u8;
+        }
+        Self { samples }
+    }
+
+    /// Square wave high for the `duty` fraction of the cycle.
+    pub fn square(duty: f32) -> Self {
+        Self::from_fn(|t| if t < duty { 1.0 } else { -1.0 })
+    }
+
+    pub fn saw() -> Self {
+        Self::from_fn(|t| 2.0 * t - 1.0)
+    }
+
+    pub fn triangle() -> Self {
+        Self::from_fn(|t| 1.0 - 4.0 * (t - 0.5).abs())
+    }
+
+    pub fn sine() -> Self {
+        Self::from_fn(|t| (2.0 * PI * t).sin())
+    }
+
+    pub fn noise(rng: &mut Rng) -> Self {
+        let mut samples = [0; WAVEFORM_SAMPLES];
+        for sample in samples.iter_mut() {
+            *sample = rng.range(0, MAX_SAMPLE as i32 + 1) as u8;
+        }
+        Self { samples }
+    }
+
+    /// Sum of sine harmonics, `amplitudes[0]` is the fundamental.
+    /// The result is normalised to the full sample range.
+    pub fn harmonics(amplitudes: &[f32]) -> Self {
+        let sum = |t: f32| -> f32 {
+            amplitudes
+                .iter()
+                .enumerate()
+                .map(|(n, a)| a * (2.0 * PI * t * (n + 1) as f32).sin())
+                .sum()
+        };
+        let peak = (0..WAVEFORM_SAMPLES)
+            .map(|i| sum(i as f32 / WAVEFORM_SAMPLES as f32).abs())
+            .fold(0.0, f32::max);
+        if peak == 0.0 {
+            return Self::from_fn(|_| 0.0);
+        }
+        Self::from_fn(|t| sum(t) / peak)
+    }
+
+    /// Unpack from 16 bytes, low nibble first.
+    pub fn from_bytes(bytes: &[u8; WAVEFORM_SIZE]) -> Self {
+        let mut samples = [0; WAVEFORM_SAMPLES];
+        for (i, byte) in bytes.iter().enumerate() {
+            samples[i * 2] = byte & 0x0f;
+            samples[i * 2 + 1] = byte >> 4;
+        }
+        Self { samples }
+    }
+
+    /// Pack into 16 bytes, low nibble first.
+    pub fn to_bytes(self) -> [u8; WAVEFORM_SIZE] {
+        let mut bytes = [0; WAVEFORM_SIZE];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = (self.samples[i * 2] & 0x0f) | ((self.samples[i * 2 + 1] & 0x0f) << 4);
+        }
+        bytes
+    }
+
+    /// Read waveform `id` from `WAVEFORMS`.
+    pub fn read(id: usize) -> Result<Self, Tic80Error> {
+        let offset = waveform_offset(id)?;
+        let mut bytes = [0; WAVEFORM_SIZE];
+        unsafe {
+            let waveforms = &*WAVEFORMS;
+            bytes.copy_from_slice(&waveforms[offset..offset + WAVEFORM_SIZE]);
+        }
+        Ok(Self::from_bytes(&bytes))
+    }
+
+    /// Write to waveform `id` in `WAVEFORMS`.
+    pub fn write(&self, id: usize) -> Result<(), Tic80Error> {
+        let offset = waveform_offset(id)?;
+        unsafe {
+            let waveforms = &mut *WAVEFORMS;
+            waveforms[offset..offset + WAVEFORM_SIZE].copy_from_slice(&self.to_bytes());
+        }
+        Ok(())
+    }
+
+    /// Flip the wave upside down.
+    pub fn inverted(mut self) -> Self {
+        for sample in self.samples.iter_mut() {
+            *sample = MAX_SAMPLE - *sample;
+        }
+        self
+    }
+
+    /// Scale towards the middle level, `amount` 0.0..=1.0.
+    pub fn scaled(mut self, amount: f32) -> Self {
+        let mid = MAX_SAMPLE as f32 / 2.0;
+        for sample in self.samples.iter_mut() {
+            *sample = (mid + (*sample as f32 - mid) * amount.clamp(0.0, 1.0) + 0.5) as u8;
+        }
+        self
+    }
+}
+
+fn waveform_offset(id: usize) -> Result<usize, Tic80Error> {
+    if id >= WAVEFORM_COUNT {
+        return Err(Tic80Error::OutOfRange {
+            index: id,
+            len: WAVEFORM_COUNT,
+        });
+    }
+    Ok(id * WAVEFORM_SIZE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bytes_are_packed_low_nibble_first() {
+        let mut samples = [0; WAVEFORM_SAMPLES];
+        samples[0] = 0x3;
+        samples[1] = 0xa;
+        samples[31] = 0xf;
+        let bytes = Waveform { samples }.to_bytes();
+        assert_eq!(bytes[0], 0xa3);
+        assert_eq!(bytes[15], 0xf0);
+        assert_eq!(Waveform::from_bytes(&bytes).samples, samples);
+    }
+
+    #[test]
+    fn square_wave_duty() {
+        let wave = Waveform::square(0.25);
+        assert!(wave.samples[..8].iter().all(|&s| s == 15));
+        assert!(wave.samples[8..].iter().all(|&s| s == 0));
+    }
+
+    #[test]
+    fn shapes_stay_in_range() {
+        let mut rng = Rng::new(1);
+        for wave in [
+            Waveform::saw(),
+            Waveform::triangle(),
+            Waveform::sine(),
+            Waveform::noise(&mut rng),
+            Waveform::harmonics(&[1.0, 0.5, 0.25]),
+        ] {
+            assert!(wave.samples.iter().all(|&s| s <= MAX_SAMPLE));
+        }
+        assert_eq!(Waveform::saw().samples[0], 0);
+        assert_eq!(Waveform::sine().samples[8], 15);
+        assert_eq!(Waveform::triangle().samples[16], 15);
+    }
+
+    #[test]
+    fn inverted_and_scaled() {
+        let wave = Waveform::square(0.5);
+        assert_eq!(wave.inverted().samples[0], 0);
+        assert_eq!(wave.inverted().inverted(), wave);
+        let flat = wave.scaled(0.0);
+        assert!(flat.samples.iter().all(|&s| s == 8));
+    }
+
+    #[test]
+    fn waveform_ids_are_checked() {
+        assert_eq!(waveform_offset(15).unwrap(), 240);
+        assert!(matches!(
+            waveform_offset(16),
+            Err(Tic80Error::OutOfRange { index: 16, len: 16 })
+        ));
+    }
+}